@@ -0,0 +1,594 @@
+//! Bazel query frontend, for workspaces using `rules_rust`.
+//!
+//! Bazel outputs rules in the `jsonproto` format (or `streamed_jsonproto`, with
+//! one target per line), where every attribute is an entry with a typed value.
+//! Rules are mapped onto the Buck rule model:
+//! - `rust_library`, `rust_binary` and `rust_test` correspond to their Buck
+//!   equivalents, whereas `rust_proc_macro` is a library with `proc_macro` set,
+//! - `crate_name` and `crate_features` correspond to `crate` and `features`,
+//! - `proc_macro_deps` are regular dependencies,
+//! - a `rust_test` with `crate` set builds the unit tests of that crate, so it
+//!   inherits its sources and dependencies and is listed in its `tests`.
+//!
+//! Labels lose their leading `@`, so that the ones from the main repository
+//! (`@//foo:bar` or `@@//foo:bar`) are the same as in Buck and the ones from
+//! external repositories look like Buck cells. Rules from external repositories
+//! are unsupported, as their sources aren't part of the workspace. Visibility
+//! specifications are translated to Buck target patterns.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::de;
+use serde_json::{Map, Value};
+
+use crate::buck::{self, BuildTarget, BuildTargetPattern, Rules};
+use crate::query::QueryBackend;
+
+#[derive(Deserialize, Debug)]
+struct QueryResult {
+    #[serde(default)]
+    target: Vec<Target>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Target {
+    #[serde(rename = "type")]
+    typ: String,
+    rule: Option<Rule>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Rule {
+    name: String,
+    rule_class: String,
+    #[serde(default)]
+    attribute: Vec<Attribute>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Attribute {
+    name: String,
+    string_value: Option<String>,
+    #[serde(default)]
+    string_list_value: Vec<String>,
+    boolean_value: Option<bool>,
+}
+
+impl Rule {
+    fn attr(&self, name: &str) -> Option<&Attribute> {
+        self.attribute.iter().find(|attr| attr.name == name)
+    }
+
+    fn string(&self, name: &str) -> Option<&str> {
+        self.attr(name)
+            .and_then(|attr| attr.string_value.as_deref())
+            .filter(|value| !value.is_empty())
+    }
+
+    fn strings(&self, name: &str) -> &[String] {
+        self.attr(name)
+            .map(|attr| attr.string_list_value.as_slice())
+            .unwrap_or_default()
+    }
+
+    fn boolean(&self, name: &str) -> Option<bool> {
+        self.attr(name).and_then(|attr| attr.boolean_value)
+    }
+}
+
+/// Strips the leading `@` from the label.
+fn normalize_label(label: &str) -> String {
+    label.trim_start_matches('@').to_owned()
+}
+
+/// Returns the Bazel label of the target (or pattern), where cells are
+/// repositories.
+fn label(target: impl fmt::Display) -> String {
+    let label = target.to_string();
+    let repo = label.split("//").next().unwrap_or_default();
+    if repo.is_empty() {
+        label
+    } else if repo.contains(['~', '+']) {
+        // Only canonical repository names contain these
+        format!("@@{}", label)
+    } else {
+        format!("@{}", label)
+    }
+}
+
+/// Returns the path of the source file label, relative to the package.
+fn source_path(label: &str) -> String {
+    match label.split_once(':') {
+        Some((_, path)) => path.to_owned(),
+        None => label.to_owned(),
+    }
+}
+
+/// Translates the visibility specification to a Buck target pattern.
+fn visibility_pattern(label: &str) -> Option<String> {
+    let label = normalize_label(label);
+    match label.as_str() {
+        "//visibility:public" => Some(String::from("PUBLIC")),
+        "//visibility:private" => None,
+        _ => match label.split_once(':') {
+            Some((package, "__pkg__")) => Some(format!("{}:", package)),
+            Some((package, "__subpackages__")) => Some(format!("{}/...", package)),
+            _ => Some(label),
+        },
+    }
+}
+
+fn rule_attrs(rule: &Rule) -> Result<(String, Map<String, Value>), serde_json::Error> {
+    let typ = match rule.rule_class.as_str() {
+        "rust_library" | "rust_proc_macro" => "rust_library",
+        "rust_binary" => "rust_binary",
+        "rust_test" => "rust_test",
+        _ => "",
+    };
+    let target = normalize_label(&rule.name)
+        .parse::<BuildTarget>()
+        .map_err(de::Error::custom)?;
+    let labels = |attrs: &[&str]| {
+        attrs
+            .iter()
+            .flat_map(|attr| rule.strings(attr))
+            .map(|label| normalize_label(label))
+            .collect::<Vec<_>>()
+    };
+
+    let deps = labels(&["deps", "proc_macro_deps"]);
+    let mut attrs = Map::new();
+    // Sources of external repositories live in Bazel's output base, outside
+    // of the workspace, so their crates can't be translated to path packages
+    let supported = !typ.is_empty() && target.cell().is_none();
+    let typ = match target.cell() {
+        _ if supported => typ.to_owned(),
+        Some(_) => format!("external_{}", rule.rule_class),
+        None => rule.rule_class.clone(),
+    };
+    attrs.insert("buck.type".into(), typ.into());
+    attrs.insert(
+        "buck.base_path".into(),
+        target.base_path().to_string_lossy().into(),
+    );
+    attrs.insert("buck.direct_dependencies".into(), deps.clone().into());
+    attrs.insert("name".into(), target.name().into());
+    attrs.insert("deps".into(), deps.into());
+    attrs.insert(
+        "visibility".into(),
+        rule.strings("visibility")
+            .iter()
+            .filter_map(|label| visibility_pattern(label))
+            .collect::<Vec<_>>()
+            .into(),
+    );
+
+    if supported {
+        let srcs = rule.strings("srcs").iter().map(|src| source_path(src));
+        let srcs = srcs.collect::<Vec<_>>();
+        // rules_rust uses the only source file as the crate root, whatever its
+        // name is
+        let crate_root = match (rule.string("crate_root"), srcs.as_slice()) {
+            (Some(crate_root), _) => Some(source_path(crate_root)),
+            (None, [src]) => Some(src.clone()),
+            (None, _) => None,
+        };
+        if let Some(crate_root) = crate_root {
+            attrs.insert("crate_root".into(), crate_root.into());
+        }
+        attrs.insert("srcs".into(), srcs.into());
+        if let Some(krate) = rule.string("crate_name") {
+            attrs.insert("crate".into(), krate.into());
+        }
+        attrs.insert("features".into(), rule.strings("crate_features").into());
+        attrs.insert("rustc_flags".into(), rule.strings("rustc_flags").into());
+    }
+    match rule.rule_class.as_str() {
+        "rust_proc_macro" => {
+            attrs.insert("proc_macro".into(), true.into());
+        }
+        "rust_test" => {
+            if let Some(framework) = rule.boolean("use_libtest_harness") {
+                attrs.insert("framework".into(), framework.into());
+            }
+            if let Some(krate) = rule.string("crate") {
+                // Only used to inherit from the crate, removed afterwards
+                attrs.insert("bazel.crate".into(), normalize_label(krate).into());
+            }
+        }
+        _ => {}
+    }
+
+    Ok((target.to_string(), attrs))
+}
+
+/// Makes unit tests (`rust_test` with `crate` set) inherit sources and
+/// dependencies of the crate under test and lists them in its `tests`.
+fn inherit_crates(
+    rules: &mut BTreeMap<String, Map<String, Value>>,
+) -> Result<(), serde_json::Error> {
+    let unit_tests = rules
+        .iter()
+        .filter_map(|(target, attrs)| {
+            let krate = attrs.get("bazel.crate")?.as_str()?;
+            Some((target.clone(), krate.to_owned()))
+        })
+        .collect::<Vec<_>>();
+
+    for (test, krate) in unit_tests {
+        rules.get_mut(&test).unwrap().remove("bazel.crate");
+        let krate_attrs = match rules.get_mut(&krate) {
+            Some(attrs) => {
+                let tests = attrs.entry("tests").or_insert_with(|| Value::Array(vec![]));
+                if let Value::Array(tests) = tests {
+                    tests.push(test.clone().into());
+                }
+                attrs.clone()
+            }
+            None => continue,
+        };
+
+        let attrs = rules.get_mut(&test).unwrap();
+        let same_package = attrs.get("buck.base_path") == krate_attrs.get("buck.base_path");
+        let has_srcs = attrs["srcs"]
+            .as_array()
+            .is_some_and(|srcs| !srcs.is_empty());
+        if same_package && !has_srcs {
+            if let Some(srcs) = krate_attrs.get("srcs") {
+                attrs.insert(String::from("srcs"), srcs.clone());
+            }
+            // Tests look for a different default crate root than libraries do
+            let krate_rule = vec![(krate.clone(), Value::Object(krate_attrs.clone()))]
+                .into_iter()
+                .collect::<Map<_, _>>();
+            let krate_rule = buck::from_value(krate_rule.into())?;
+            if let Some(crate_root) = krate_rule
+                .values()
+                .next()
+                .and_then(|rule| rule.typ.crate_root())
+            {
+                attrs.insert(
+                    String::from("crate_root"),
+                    crate_root.to_string_lossy().into(),
+                );
+            }
+        }
+        for key in &["deps", "buck.direct_dependencies"] {
+            if let (Some(Value::Array(deps)), Some(Value::Array(krate_deps))) =
+                (attrs.get_mut(*key), krate_attrs.get(*key))
+            {
+                let missing = krate_deps
+                    .iter()
+                    .filter(|dep| !deps.contains(dep))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                deps.extend(missing);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Parses Bazel `jsonproto` or `streamed_jsonproto` query output.
+pub fn from_bytes(bytes: &[u8]) -> Result<Rules, serde_json::Error> {
+    let mut targets = vec![];
+    for value in serde_json::Deserializer::from_slice(bytes).into_iter::<Value>() {
+        let value = value?;
+        if value.get("target").is_some() {
+            targets.extend(serde_json::from_value::<QueryResult>(value)?.target);
+        } else {
+            targets.push(serde_json::from_value::<Target>(value)?);
+        }
+    }
+
+    let mut rules = targets
+        .iter()
+        .filter(|target| target.typ == "RULE")
+        .filter_map(|target| target.rule.as_ref().map(rule_attrs))
+        .collect::<Result<BTreeMap<_, _>, _>>()?;
+    inherit_crates(&mut rules)?;
+
+    let rules = rules
+        .into_iter()
+        .map(|(target, attrs)| (target, Value::Object(attrs)))
+        .collect();
+    buck::from_value(Value::Object(rules))
+}
+
+/// Whether the directory belongs to a Bazel workspace.
+pub fn is_bazel_workspace(dir: &Path) -> bool {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_owned());
+    dir.ancestors().any(|dir| {
+        ["MODULE.bazel", "WORKSPACE", "WORKSPACE.bazel"]
+            .iter()
+            .any(|file| dir.join(file).is_file())
+    })
+}
+
+pub fn bazel_command(dir: impl AsRef<Path>, query: impl AsRef<str>) -> Command {
+    let mut cmd = Command::new("bazel");
+    cmd.arg("query")
+        .arg(query.as_ref())
+        // Toolchains and other implicit dependencies aren't translated
+        .arg("--noimplicit_deps")
+        .arg("--output=streamed_jsonproto")
+        .current_dir(dir.as_ref());
+    cmd
+}
+
+pub fn bazel_root(cwd: impl AsRef<Path>) -> Result<PathBuf, failure::Error> {
+    let mut cmd = Command::new("bazel");
+    cmd.args(["info", "workspace"]).current_dir(cwd.as_ref());
+
+    Ok(PathBuf::from(String::from_utf8(buck::run(cmd)?)?.trim()))
+}
+
+/// Query backend running the Bazel CLI.
+#[derive(Debug)]
+pub struct BazelCli {
+    dir: PathBuf,
+    root: Option<PathBuf>,
+    save_json: Option<PathBuf>,
+}
+
+impl BazelCli {
+    /// Runs Bazel inside the given directory.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        BazelCli {
+            dir: dir.into(),
+            root: None,
+            save_json: None,
+        }
+    }
+
+    /// Uses the given root instead of the one reported by Bazel.
+    pub fn with_root(self, root: impl Into<PathBuf>) -> Self {
+        BazelCli {
+            root: Some(root.into()),
+            ..self
+        }
+    }
+
+    /// Saves the raw output of the `deps` query to a file, so that it can be
+    /// replayed later with `query::Recorded`.
+    pub fn save_json(self, path: impl Into<PathBuf>) -> Self {
+        BazelCli {
+            save_json: Some(path.into()),
+            ..self
+        }
+    }
+
+    fn query(&self, query: &str) -> Result<Vec<u8>, failure::Error> {
+        buck::run(bazel_command(&self.dir, query))
+    }
+}
+
+impl QueryBackend for BazelCli {
+    fn root(&self) -> Result<PathBuf, failure::Error> {
+        match &self.root {
+            Some(root) => Ok(root.clone()),
+            None => bazel_root(&self.dir),
+        }
+    }
+
+    fn query_deps(&self, patterns: &[BuildTargetPattern]) -> Result<Rules, failure::Error> {
+        let json = self.query(&buck::deps_query(patterns.iter().map(label)))?;
+        if let Some(path) = &self.save_json {
+            fs::write(path, &json)?;
+        }
+        Ok(from_bytes(&json)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(s: &str) -> BuildTarget {
+        s.parse().unwrap()
+    }
+
+    fn pattern(s: &str) -> BuildTargetPattern {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn translate_visibility() {
+        assert_eq!(
+            visibility_pattern("//visibility:public"),
+            Some(String::from("PUBLIC"))
+        );
+        assert_eq!(visibility_pattern("//visibility:private"), None);
+        assert_eq!(
+            visibility_pattern("@//foo:__pkg__"),
+            Some(String::from("//foo:"))
+        );
+        assert_eq!(
+            visibility_pattern("//foo:__subpackages__"),
+            Some(String::from("//foo/..."))
+        );
+    }
+
+    #[test]
+    fn query_labels() {
+        let patterns = ["//foo/...", "rules_rust//:lib", "rules_rust~//:lib"]
+            .map(|pattern| pattern.parse::<BuildTargetPattern>().unwrap());
+        assert_eq!(
+            buck::deps_query(patterns.iter().map(label)),
+            "deps(set(//foo/... @rules_rust//:lib @@rules_rust~//:lib))"
+        );
+    }
+
+    #[test]
+    fn parse_jsonproto() -> Result<(), failure::Error> {
+        let input = r#"{
+          "target": [{
+            "type": "RULE",
+            "rule": {
+              "name": "//foo:lib",
+              "ruleClass": "rust_library",
+              "location": "/workspace/foo/BUILD.bazel:3:13",
+              "attribute": [{
+                "name": "srcs",
+                "type": "LABEL_LIST",
+                "stringListValue": ["//foo:src/lib.rs", "//foo:src/util.rs"],
+                "explicitlySpecified": true
+              }, {
+                "name": "crate_name",
+                "type": "STRING",
+                "stringValue": "mylib",
+                "explicitlySpecified": true
+              }, {
+                "name": "crate_features",
+                "type": "STRING_LIST",
+                "stringListValue": ["std"],
+                "explicitlySpecified": true
+              }, {
+                "name": "proc_macro_deps",
+                "type": "LABEL_LIST",
+                "stringListValue": ["//macros:macros"],
+                "explicitlySpecified": true
+              }, {
+                "name": "visibility",
+                "type": "STRING_LIST",
+                "stringListValue": ["//visibility:public"]
+              }]
+            }
+          }, {
+            "type": "RULE",
+            "rule": {
+              "name": "//macros:macros",
+              "ruleClass": "rust_proc_macro",
+              "attribute": [{
+                "name": "srcs",
+                "type": "LABEL_LIST",
+                "stringListValue": ["//macros:lib.rs"]
+              }]
+            }
+          }, {
+            "type": "RULE",
+            "rule": {
+              "name": "//foo:lib_test",
+              "ruleClass": "rust_test",
+              "attribute": [{
+                "name": "crate",
+                "type": "LABEL",
+                "stringValue": "//foo:lib"
+              }, {
+                "name": "use_libtest_harness",
+                "type": "BOOLEAN",
+                "booleanValue": true
+              }]
+            }
+          }, {
+            "type": "SOURCE_FILE",
+            "sourceFile": {
+              "name": "//foo:src/lib.rs"
+            }
+          }]
+        }"#;
+
+        let rules = from_bytes(input.as_bytes())?;
+        assert_eq!(rules.len(), 3);
+
+        let lib = &rules[&target("//foo:lib")];
+        assert_eq!(lib.typ.name(), "rust_library");
+        assert_eq!(lib.base_path, PathBuf::from("foo"));
+        assert_eq!(lib.typ.krate(), Some("mylib"));
+        assert_eq!(lib.typ.crate_root(), Some(Path::new("src/lib.rs")));
+        assert_eq!(lib.typ.features(), ["std"]);
+        assert_eq!(lib.typ.tests(), [target("//foo:lib_test")]);
+        assert_eq!(lib.common.deps, vec![target("//macros:macros")]);
+        assert_eq!(lib.common.visibility, vec![pattern("PUBLIC")]);
+
+        let macros = &rules[&target("//macros:macros")];
+        assert!(macros.typ.is_library());
+        assert!(macros.typ.is_proc_macro());
+        assert_eq!(macros.typ.krate(), Some("macros"));
+
+        let test = &rules[&target("//foo:lib_test")];
+        assert!(test.typ.is_test());
+        assert_eq!(test.typ.test_framework(), Some(true));
+        assert_eq!(test.typ.crate_root(), Some(Path::new("src/lib.rs")));
+        assert_eq!(test.common.deps, vec![target("//macros:macros")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_streamed_jsonproto() -> Result<(), failure::Error> {
+        let input = concat!(
+            r#"{"type":"RULE","rule":{"name":"//bin:bin","ruleClass":"rust_binary","#,
+            r#""attribute":[{"name":"srcs","stringListValue":["//bin:main.rs"]},"#,
+            r#"{"name":"deps","stringListValue":["@//lib:lib"]}]}}"#,
+            "\n",
+            r#"{"type":"RULE","rule":{"name":"//lib:lib","ruleClass":"rust_library","#,
+            r#""attribute":[{"name":"srcs","stringListValue":["//lib:lib.rs"]}]}}"#,
+            "\n",
+            r#"{"type":"RULE","rule":{"name":"//lib:gen","ruleClass":"genrule"}}"#,
+            "\n",
+        );
+
+        let rules = from_bytes(input.as_bytes())?;
+        assert_eq!(rules.len(), 3);
+        assert_eq!(
+            rules[&target("//bin:bin")].common.deps,
+            vec![target("//lib:lib")]
+        );
+        assert_eq!(rules[&target("//lib:lib")].typ.krate(), Some("lib"));
+        assert!(!rules[&target("//lib:gen")].typ.is_supported());
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_bzlmod_labels() -> Result<(), failure::Error> {
+        let input = concat!(
+            r#"{"type":"RULE","rule":{"name":"//bin:bin","ruleClass":"rust_binary","#,
+            r#""attribute":[{"name":"srcs","stringListValue":["//bin:main.rs"]},"#,
+            r#"{"name":"deps","stringListValue":["@@rules_rust~~crate~crates__serde-1.0.197//:serde"]}]}}"#,
+            "\n",
+            r#"{"type":"RULE","rule":{"name":"@@rules_rust~~crate~crates__serde-1.0.197//:serde","#,
+            r#""ruleClass":"rust_library","attribute":[{"name":"srcs","#,
+            r#""stringListValue":["@@rules_rust~~crate~crates__serde-1.0.197//:src/lib.rs"]}]}}"#,
+            "\n",
+        );
+
+        let rules = from_bytes(input.as_bytes())?;
+        let serde = target("rules_rust~~crate~crates__serde-1.0.197//:serde");
+        assert_eq!(rules[&target("//bin:bin")].common.deps, vec![serde.clone()]);
+        assert!(!rules[&serde].typ.is_supported());
+        assert_eq!(
+            label(&serde),
+            "@@rules_rust~~crate~crates__serde-1.0.197//:serde"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn single_src_crate_root() -> Result<(), failure::Error> {
+        let input = concat!(
+            r#"{"type":"RULE","rule":{"name":"//server:server","ruleClass":"rust_binary","#,
+            r#""attribute":[{"name":"srcs","stringListValue":["//server:server_main.rs"]}]}}"#,
+            "\n",
+            r#"{"type":"RULE","rule":{"name":"//lib:lib","ruleClass":"rust_library","#,
+            r#""attribute":[{"name":"srcs","stringListValue":["//lib:a.rs","//lib:b.rs"]}]}}"#,
+            "\n",
+        );
+
+        let rules = from_bytes(input.as_bytes())?;
+        let server = &rules[&target("//server:server")];
+        assert_eq!(server.typ.crate_root(), Some(Path::new("server_main.rs")));
+        assert_eq!(rules[&target("//lib:lib")].typ.crate_root(), None);
+
+        Ok(())
+    }
+}
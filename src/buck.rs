@@ -1,14 +1,16 @@
 use std::collections::HashMap;
 use std::fmt;
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
-pub type BuildTarget = String;
-pub type BuildTargetPattern = String;
+use crate::query::QueryBackend;
+
+pub use crate::target::{BuildTarget, BuildTargetPattern};
 
 pub type Rules = HashMap<BuildTarget, BuildRule>;
 
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct CommonBuildRule {
     /// The name of the build rule, which must be unique within a build file.
     pub name: String,
@@ -23,7 +25,7 @@ pub struct CommonBuildRule {
 }
 
 #[allow(clippy::large_enum_variant)]
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "buck.type")]
 #[serde(rename_all = "snake_case")]
 pub enum BuildRuleType {
@@ -56,15 +58,75 @@ impl BuildRuleType {
         }
     }
 
-    pub fn is_supported(&self) -> bool {
+    pub fn features(&self) -> &[String] {
         match self {
-            BuildRuleType::RustBinary(..)
-            | BuildRuleType::RustLibrary(..)
-            | BuildRuleType::RustTest(..) => true,
+            BuildRuleType::RustBinary(binary) => &binary.features,
+            BuildRuleType::RustLibrary(library) => &library.features,
+            BuildRuleType::RustTest(test) => &test.features,
+            _ => &[],
+        }
+    }
+
+    pub fn rustc_flags(&self) -> &[String] {
+        match self {
+            BuildRuleType::RustBinary(binary) => &binary.rustc_flags,
+            BuildRuleType::RustLibrary(library) => &library.rustc_flags,
+            BuildRuleType::RustTest(test) => &test.rustc_flags,
+            _ => &[],
+        }
+    }
+
+    pub fn linker_flags(&self) -> &[String] {
+        match self {
+            BuildRuleType::RustBinary(binary) => &binary.linker_flags,
+            BuildRuleType::RustTest(test) => &test.linker_flags,
+            _ => &[],
+        }
+    }
+
+    pub fn tests(&self) -> &[BuildTarget] {
+        match self {
+            BuildRuleType::RustBinary(binary) => &binary.tests,
+            BuildRuleType::RustLibrary(library) => &library.tests,
+            _ => &[],
+        }
+    }
+
+    pub fn licenses(&self) -> &[String] {
+        match self {
+            BuildRuleType::RustBinary(binary) => &binary.licenses,
+            BuildRuleType::RustLibrary(library) => &library.licenses,
+            BuildRuleType::RustTest(test) => &test.licenses,
+            BuildRuleType::PrebuiltRustLibrary(preb) => &preb.licenses,
+            _ => &[],
+        }
+    }
+
+    pub fn is_proc_macro(&self) -> bool {
+        match self {
+            BuildRuleType::RustLibrary(library) => library.proc_macro,
             _ => false,
         }
     }
 
+    /// Whether the test uses the standard test framework or `None` if the rule
+    /// is not a test.
+    pub fn test_framework(&self) -> Option<bool> {
+        match self {
+            BuildRuleType::RustTest(test) => Some(test.framework),
+            _ => None,
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        matches!(
+            self,
+            BuildRuleType::RustBinary(..)
+                | BuildRuleType::RustLibrary(..)
+                | BuildRuleType::RustTest(..)
+        )
+    }
+
     pub fn name(&self) -> &str {
         match self {
             BuildRuleType::RustBinary(..) => "rust_binary",
@@ -76,24 +138,21 @@ impl BuildRuleType {
     }
 
     pub fn is_library(&self) -> bool {
-        match self {
-            BuildRuleType::RustLibrary(..) | BuildRuleType::PrebuiltRustLibrary(..) => true,
-            _ => false,
-        }
+        matches!(
+            self,
+            BuildRuleType::RustLibrary(..) | BuildRuleType::PrebuiltRustLibrary(..)
+        )
     }
 
     pub fn is_binary(&self) -> bool {
-        match self {
-            BuildRuleType::RustBinary(..) | BuildRuleType::RustTest(..) => true,
-            _ => false,
-        }
+        matches!(
+            self,
+            BuildRuleType::RustBinary(..) | BuildRuleType::RustTest(..)
+        )
     }
 
     pub fn is_test(&self) -> bool {
-        match self {
-            BuildRuleType::RustTest(..) => true,
-            _ => false,
-        }
+        matches!(self, BuildRuleType::RustTest(..))
     }
 
     #[rustfmt::skip]
@@ -139,7 +198,7 @@ impl BuildRuleType {
 /// libraries it produces metadata for consumers of the library. When building
 /// with check, extra compiler flags from the rust.rustc_check_flags are added
 /// to the compiler's command line options, to allow for extra warnings, etc.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(default)]
 pub struct RustBinaryRule {
     /// The set of Rust source files to be compiled by this rule.
@@ -207,7 +266,7 @@ impl Default for RustBinaryRule {
 /// libraries it produces metadata for consumers of the library. When building
 /// with check, extra compiler flags from the rust.rustc_check_flags are added
 /// to the compiler's command line options, to allow for extra warnings, etc.
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(default)]
 pub struct RustLibraryRule {
     /// The set of Rust source files to be compiled by this rule.
@@ -231,6 +290,8 @@ pub struct RustLibraryRule {
     crate_root: PathBuf,
     /// Controls how a library should be linked.
     preferred_linkage: PreferredLinkage,
+    /// Set to true to build the library as a procedural macro.
+    proc_macro: bool,
     /// List of build targets that identify tests that exercise this target.
     tests: Vec<BuildTarget>,
     /// Set of license files for this library. To get the list of license files
@@ -245,7 +306,7 @@ pub struct RustLibraryRule {
 
 /// A rust_test() rule builds a Rust test native executable from the supplied
 /// set of Rust source files and dependencies and runs this test.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(default)]
 pub struct RustTestRule {
     /// The set of Rust source files to be compiled by this rule.
@@ -265,6 +326,8 @@ pub struct RustTestRule {
     features: Vec<String>,
     /// The set of additional compiler flags to pass to `rustc`.
     rustc_flags: Vec<String>,
+    /// The set of additional flags to pass to the linker.
+    linker_flags: Vec<String>,
     #[serde(rename = "crate")]
     /// Set the generated crate name (for libraries) or executable name (for
     /// binaries), independent of the rule name. Defaults to the rule name.
@@ -292,6 +355,7 @@ impl Default for RustTestRule {
             srcs: Default::default(),
             features: Default::default(),
             rustc_flags: Default::default(),
+            linker_flags: Default::default(),
             krate: Default::default(),
             crate_root: Default::default(),
             link_style: Default::default(),
@@ -303,7 +367,7 @@ impl Default for RustTestRule {
 
 /// A prebuilt_rust_library() specifies a pre-built Rust crate, and any
 /// dependencies it may have on other crates (typically also prebuilt).
-#[derive(Serialize, Deserialize, Debug, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(default)]
 pub struct PrebuiltRustLibraryRule {
     /// Path to the precompiled Rust crate - typically of the form
@@ -326,41 +390,35 @@ pub struct PrebuiltRustLibraryRule {
 
 /// Determines whether to build and link this rule's dependencies statically or
 /// dynamically.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 #[serde(rename_all = "snake_case")]
 pub enum LinkStyle {
+    #[default]
     Static,
     StaticPic,
     Shared,
 }
 
-impl Default for LinkStyle {
-    fn default() -> Self {
-        LinkStyle::Static
-    }
-}
-
 /// Controls how a library should be linked.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "snake_case")]
 pub enum PreferredLinkage {
     /// The library will be linked based on its dependents `link_style`.
+    #[default]
+    #[serde(alias = "Any")]
     Any,
     /// The library will be always be linked as a shared library.
+    #[serde(alias = "Shared")]
     Shared,
     /// The library will be linked as a static library.
     /// Note: since shared libraries re-export its dependencies, depending on
     /// multiple shared libraries which themselves have overlapping static
     /// dependencies will cause duplicate symbols.
+    #[serde(alias = "Static")]
     Static,
 }
 
-impl Default for PreferredLinkage {
-    fn default() -> Self {
-        PreferredLinkage::Any
-    }
-}
-
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct BuildRule {
     #[serde(rename = "buck.base_path")]
     pub base_path: PathBuf,
@@ -372,10 +430,10 @@ pub struct BuildRule {
     pub typ: BuildRuleType,
 }
 
-pub fn buck_command(dir: impl AsRef<Path>, rule: impl AsRef<str>) -> Command {
+pub fn buck_command(dir: impl AsRef<Path>, query: impl AsRef<str>) -> Command {
     let mut cmd = Command::new("buck");
     cmd.arg("query")
-        .arg(format!("deps({})", rule.as_ref()))
+        .arg(query.as_ref())
         .arg("--output-attributes")
         .arg(".*")
         .current_dir(dir.as_ref());
@@ -383,7 +441,13 @@ pub fn buck_command(dir: impl AsRef<Path>, rule: impl AsRef<str>) -> Command {
 }
 
 pub fn from_bytes(bytes: &[u8]) -> Result<Rules, serde_json::Error> {
-    let mut rules: Rules = serde_json::from_slice(bytes)?;
+    from_value(serde_json::from_slice(bytes)?)
+}
+
+/// Parses rules from an already deserialized query output, e.g. one which was
+/// normalized from another build system.
+pub fn from_value(value: serde_json::Value) -> Result<Rules, serde_json::Error> {
+    let mut rules: Rules = serde_json::from_value(value)?;
 
     // Adjust default `crate` field to rule name, if applies
     for rule in rules.values_mut() {
@@ -395,33 +459,88 @@ pub fn from_bytes(bytes: &[u8]) -> Result<Rules, serde_json::Error> {
     Ok(rules)
 }
 
-pub fn query_rules(dir: impl AsRef<Path>, rule: impl AsRef<str>) -> Result<Rules, failure::Error> {
-    let output = buck_command(dir, rule).output()?;
-    if !output.status.success() {
-        return Err(BuckError(
-            output.status,
-            String::from_utf8_lossy(&output.stderr).to_string(),
-        )
-        .into());
+/// Query backend running the Buck CLI.
+#[derive(Debug)]
+pub struct BuckCli {
+    dir: PathBuf,
+    root: Option<PathBuf>,
+    save_json: Option<PathBuf>,
+}
+
+impl BuckCli {
+    /// Runs Buck inside the given directory.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        BuckCli {
+            dir: dir.into(),
+            root: None,
+            save_json: None,
+        }
+    }
+
+    /// Uses the given root instead of the one reported by Buck.
+    pub fn with_root(self, root: impl Into<PathBuf>) -> Self {
+        BuckCli {
+            root: Some(root.into()),
+            ..self
+        }
+    }
+
+    /// Saves the raw output of the `deps` query to a file, so that it can be
+    /// replayed later with `query::Recorded`.
+    pub fn save_json(self, path: impl Into<PathBuf>) -> Self {
+        BuckCli {
+            save_json: Some(path.into()),
+            ..self
+        }
+    }
+
+    fn query(&self, query: &str) -> Result<Vec<u8>, failure::Error> {
+        run(buck_command(&self.dir, query))
+    }
+}
+
+impl QueryBackend for BuckCli {
+    fn root(&self) -> Result<PathBuf, failure::Error> {
+        match &self.root {
+            Some(root) => Ok(root.clone()),
+            None => buck_root(&self.dir),
+        }
     }
 
-    from_bytes(&output.stdout).map_err(|x| x.into())
+    fn query_deps(&self, patterns: &[BuildTargetPattern]) -> Result<Rules, failure::Error> {
+        let json = self.query(&deps_query(patterns.iter().map(ToString::to_string)))?;
+        if let Some(path) = &self.save_json {
+            fs::write(path, &json)?;
+        }
+        Ok(from_bytes(&json)?)
+    }
+}
+
+/// Returns the query for the dependencies of all the target patterns at once.
+pub fn deps_query(patterns: impl IntoIterator<Item = String>) -> String {
+    let patterns = patterns.into_iter().collect::<Vec<_>>();
+    format!("deps(set({}))", patterns.join(" "))
 }
 
 pub fn buck_root(cwd: impl AsRef<Path>) -> Result<PathBuf, failure::Error> {
-    let output = Command::new("buck")
-        .arg("root")
-        .current_dir(cwd.as_ref())
-        .output()?;
-
-    if output.status.success() {
-        Ok(PathBuf::from(String::from_utf8(output.stdout)?.trim()))
-    } else {
-        Err(BuckError(
+    let mut cmd = Command::new("buck");
+    cmd.arg("root").current_dir(cwd.as_ref());
+
+    Ok(PathBuf::from(String::from_utf8(run(cmd)?)?.trim()))
+}
+
+/// Runs the Buck command, returning its standard output.
+pub fn run(mut cmd: Command) -> Result<Vec<u8>, failure::Error> {
+    let output = cmd.output()?;
+    if !output.status.success() {
+        return Err(BuckError(
             output.status,
             String::from_utf8_lossy(&output.stderr).to_string(),
-        ))?
+        )
+        .into());
     }
+
+    Ok(output.stdout)
 }
 
 #[derive(Debug)]
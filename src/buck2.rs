@@ -0,0 +1,404 @@
+//! Buck2 query frontend.
+//!
+//! Buck2 describes rules with mostly the same attributes as Buck, but its query
+//! output differs in a couple of ways:
+//! - target labels are qualified with a cell (`root//foo:bar`) and, in case of
+//!   `cquery`, are followed by the configuration (`root//foo:bar (cfg#hash)`),
+//! - `buck.type` includes the file defining the rule, e.g.
+//!   `prelude//rules.bzl:rust_library`,
+//! - there is no `buck.base_path`, and source paths are cell-qualified,
+//! - unset attributes are output as `null`.
+//!
+//! The output is normalized here, so that it can be parsed as regular `Rules`.
+//! Labels from the root cell lose the cell qualifier, so they are the same as
+//! the ones known from Buck.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::de;
+use serde_json::{Map, Value};
+
+use crate::buck::{self, BuildTarget, BuildTargetPattern, Rules};
+use crate::query::QueryBackend;
+
+/// Attributes holding build targets (or target patterns).
+const LABEL_ATTRS: &[&str] = &["deps", "tests", "visibility"];
+/// Attributes holding source paths.
+const SOURCE_ATTRS: &[&str] = &["srcs", "crate_root", "licenses"];
+
+/// Cells of a Buck2 project, mapped to their paths relative to the project
+/// root.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cells(BTreeMap<String, PathBuf>);
+
+impl Default for Cells {
+    fn default() -> Self {
+        Cells(
+            vec![(String::from("root"), PathBuf::from("."))]
+                .into_iter()
+                .collect(),
+        )
+    }
+}
+
+impl Cells {
+    /// Reads the `[cells]` section of the `.buckconfig` in the project root,
+    /// falling back to a single `root` cell if there is none.
+    pub fn read(root: &Path) -> io::Result<Cells> {
+        match fs::read_to_string(root.join(".buckconfig")) {
+            Ok(config) => Ok(Cells::parse(&config)),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(Cells::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn parse(config: &str) -> Cells {
+        let cells = buckconfig_section(config, "cells")
+            .into_iter()
+            .map(|(name, path)| (name, PathBuf::from(path)))
+            .collect::<BTreeMap<_, _>>();
+
+        if cells.is_empty() {
+            Cells::default()
+        } else {
+            Cells(cells)
+        }
+    }
+
+    fn is_root(&self, cell: &str) -> bool {
+        self.0
+            .get(cell)
+            .is_some_and(|path| path.as_os_str().is_empty() || path == Path::new("."))
+    }
+
+    /// Strips the configuration from the label and the cell qualifier, if the
+    /// target belongs to the root cell.
+    fn normalize_label(&self, label: &str) -> String {
+        let label = label.split(" (").next().unwrap_or_default().trim();
+        match label.split_once("//") {
+            Some((cell, rest)) if self.is_root(cell) => format!("//{}", rest),
+            _ => label.to_owned(),
+        }
+    }
+
+    /// Returns the path of the target's package, relative to the project root.
+    fn package_path(&self, target: &BuildTarget) -> PathBuf {
+        match target.cell().and_then(|cell| self.0.get(cell)) {
+            Some(cell_path) => cell_path.join(target.base_path()),
+            None => target.base_path().to_owned(),
+        }
+    }
+
+    /// Makes the (possibly cell-qualified) source path relative to the package.
+    fn normalize_source(&self, path: &str, base_path: &Path) -> String {
+        let path = match path.split_once("//") {
+            Some((cell, rest)) => match self.0.get(cell) {
+                Some(cell_path) if !self.is_root(cell) => {
+                    cell_path.join(rest).to_string_lossy().into_owned()
+                }
+                _ => rest.to_owned(),
+            },
+            None => return path.to_owned(),
+        };
+
+        // Sources can also be output as `package:path`
+        if let Some((_, path)) = path.split_once(':') {
+            return path.to_owned();
+        }
+        Path::new(&path)
+            .strip_prefix(base_path)
+            .map(|path| path.to_string_lossy().into_owned())
+            .unwrap_or(path)
+    }
+}
+
+/// Returns the `key = value` entries of the given `.buckconfig` section.
+fn buckconfig_section(config: &str, name: &str) -> Vec<(String, String)> {
+    let mut in_section = false;
+    let mut entries = vec![];
+    for line in config.lines().map(str::trim) {
+        if line.starts_with('#') || line.starts_with(';') || line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            in_section = line[1..line.len() - 1].trim() == name;
+        } else if in_section {
+            if let Some((key, value)) = line.split_once('=') {
+                entries.push((key.trim().to_owned(), value.trim().to_owned()));
+            }
+        }
+    }
+    entries
+}
+
+/// Whether the directory belongs to a Buck2 project rather than to a Buck one.
+///
+/// Only Buck2 marks the project root with a `.buckroot` file. Otherwise, a
+/// `.buckconfig` which sets up the Buck2 `prelude` cell is a giveaway.
+pub fn is_buck2_project(dir: &Path) -> bool {
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_owned());
+    if dir.ancestors().any(|dir| dir.join(".buckroot").is_file()) {
+        return true;
+    }
+
+    dir.ancestors()
+        .find_map(|dir| fs::read_to_string(dir.join(".buckconfig")).ok())
+        .is_some_and(|config| {
+            ["cells", "external_cells"].iter().any(|section| {
+                buckconfig_section(&config, section)
+                    .iter()
+                    .any(|(name, _)| name == "prelude")
+            })
+        })
+}
+
+/// Strips the defining file from the rule type, e.g.
+/// `prelude//rules.bzl:rust_library` becomes `rust_library`.
+fn normalize_type(typ: &str) -> &str {
+    typ.rsplit(':').next().unwrap_or(typ)
+}
+
+/// Parses Buck2 `uquery` or `cquery` output with all attributes.
+pub fn from_bytes(bytes: &[u8], cells: &Cells) -> Result<Rules, serde_json::Error> {
+    let output: BTreeMap<String, Map<String, Value>> = serde_json::from_slice(bytes)?;
+
+    let mut rules = Map::new();
+    for (label, mut attrs) in output {
+        let target = cells.normalize_label(&label);
+        let base_path = cells.package_path(&target.parse().map_err(de::Error::custom)?);
+
+        attrs.retain(|_, value| !value.is_null());
+        if let Some(Value::String(typ)) = attrs.get_mut("buck.type") {
+            *typ = normalize_type(typ).to_owned();
+        }
+        for attr in LABEL_ATTRS {
+            map_strings(attrs.get_mut(*attr), |label| cells.normalize_label(label));
+        }
+        for attr in SOURCE_ATTRS {
+            map_strings(attrs.get_mut(*attr), |path| {
+                cells.normalize_source(path, &base_path)
+            });
+        }
+
+        let mut direct_deps = attrs
+            .remove("buck.deps")
+            .or_else(|| attrs.get("deps").cloned())
+            .unwrap_or_else(|| Value::Array(vec![]));
+        map_strings(Some(&mut direct_deps), |label| cells.normalize_label(label));
+        attrs.insert(String::from("buck.direct_dependencies"), direct_deps);
+        attrs.insert(
+            String::from("buck.base_path"),
+            Value::String(base_path.to_string_lossy().into_owned()),
+        );
+
+        rules.insert(target, Value::Object(attrs));
+    }
+
+    buck::from_value(Value::Object(rules))
+}
+
+/// Applies the function to the string value or every string in the array.
+fn map_strings(value: Option<&mut Value>, f: impl Fn(&str) -> String) {
+    match value {
+        Some(Value::String(string)) => *string = f(string),
+        Some(Value::Array(values)) => {
+            for value in values {
+                if let Value::String(string) = value {
+                    *string = f(string);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn buck2_command(dir: impl AsRef<Path>, subcommand: &str, query: impl AsRef<str>) -> Command {
+    let mut cmd = Command::new("buck2");
+    cmd.arg(subcommand)
+        .arg(query.as_ref())
+        .arg("--output-all-attributes")
+        .current_dir(dir.as_ref());
+    cmd
+}
+
+pub fn buck2_root(cwd: impl AsRef<Path>) -> Result<PathBuf, failure::Error> {
+    let mut cmd = Command::new("buck2");
+    cmd.args(["root", "--kind", "project"])
+        .current_dir(cwd.as_ref());
+
+    Ok(PathBuf::from(String::from_utf8(buck::run(cmd)?)?.trim()))
+}
+
+/// Query backend running the Buck2 CLI.
+#[derive(Debug)]
+pub struct Buck2Cli {
+    dir: PathBuf,
+    root: Option<PathBuf>,
+    save_json: Option<PathBuf>,
+    configured: bool,
+}
+
+impl Buck2Cli {
+    /// Runs Buck2 inside the given directory.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Buck2Cli {
+            dir: dir.into(),
+            root: None,
+            save_json: None,
+            configured: false,
+        }
+    }
+
+    /// Uses the given root instead of the one reported by Buck2.
+    pub fn with_root(self, root: impl Into<PathBuf>) -> Self {
+        Buck2Cli {
+            root: Some(root.into()),
+            ..self
+        }
+    }
+
+    /// Saves the raw output of the `deps` query to a file, so that it can be
+    /// replayed later with `query::Recorded`.
+    pub fn save_json(self, path: impl Into<PathBuf>) -> Self {
+        Buck2Cli {
+            save_json: Some(path.into()),
+            ..self
+        }
+    }
+
+    /// Queries configured targets with `cquery` rather than `uquery`.
+    pub fn configured(self, configured: bool) -> Self {
+        Buck2Cli { configured, ..self }
+    }
+
+    fn query(&self, query: &str) -> Result<(Vec<u8>, Cells), failure::Error> {
+        let subcommand = if self.configured { "cquery" } else { "uquery" };
+        let json = buck::run(buck2_command(&self.dir, subcommand, query))?;
+        let cells = Cells::read(&self.root()?)?;
+
+        Ok((json, cells))
+    }
+}
+
+impl QueryBackend for Buck2Cli {
+    fn root(&self) -> Result<PathBuf, failure::Error> {
+        match &self.root {
+            Some(root) => Ok(root.clone()),
+            None => buck2_root(&self.dir),
+        }
+    }
+
+    fn query_deps(&self, patterns: &[BuildTargetPattern]) -> Result<Rules, failure::Error> {
+        let query = buck::deps_query(patterns.iter().map(ToString::to_string));
+        let (json, cells) = self.query(&query)?;
+        if let Some(path) = &self.save_json {
+            fs::write(path, &json)?;
+        }
+        Ok(from_bytes(&json, &cells)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(s: &str) -> BuildTarget {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_cells() {
+        let config = r#"
+[cells]
+  root = .
+  prelude = prelude
+  # comment
+  third-party = third-party/rust
+
+[buildfile]
+  name = BUCK
+"#;
+        let cells = Cells::parse(config);
+        assert!(cells.is_root("root"));
+        assert!(!cells.is_root("prelude"));
+        assert_eq!(
+            cells.package_path(&target("third-party//vendor:serde")),
+            PathBuf::from("third-party/rust/vendor")
+        );
+        assert_eq!(Cells::parse("[buildfile]\nname = BUCK"), Cells::default());
+    }
+
+    #[test]
+    fn normalize_labels() {
+        let cells = Cells::default();
+        assert_eq!(cells.normalize_label("root//foo:bar"), "//foo:bar");
+        assert_eq!(
+            cells.normalize_label("root//foo:bar (prelude//platforms:default#2c29d96c65b4379a)"),
+            "//foo:bar"
+        );
+        assert_eq!(cells.normalize_label("other//foo:bar"), "other//foo:bar");
+        assert_eq!(cells.normalize_label("PUBLIC"), "PUBLIC");
+        assert_eq!(
+            normalize_type("prelude//rules.bzl:rust_library"),
+            "rust_library"
+        );
+        assert_eq!(normalize_type("rust_binary"), "rust_binary");
+    }
+
+    #[test]
+    fn parse_cquery_output() -> Result<(), failure::Error> {
+        let input = r#"{
+            "root//foo:bin (prelude//platforms:default#2c29d96c65b4379a)": {
+                "buck.type": "prelude//rules.bzl:rust_binary",
+                "buck.deps": [
+                    "root//foo:lib (prelude//platforms:default#2c29d96c65b4379a)"
+                ],
+                "buck.package": "root//foo:BUCK",
+                "name": "bin",
+                "crate": null,
+                "crate_root": null,
+                "deps": [
+                    "root//foo:lib (prelude//platforms:default#2c29d96c65b4379a)"
+                ],
+                "srcs": [ "root//foo/src/main.rs" ],
+                "features": [ "default" ],
+                "link_style": "static_pic",
+                "visibility": [ "PUBLIC" ]
+            },
+            "root//foo:lib (prelude//platforms:default#2c29d96c65b4379a)": {
+                "buck.type": "prelude//rules.bzl:rust_library",
+                "buck.package": "root//foo:BUCK",
+                "name": "lib",
+                "crate": "mylib",
+                "deps": [],
+                "srcs": [ "root//foo:src/lib.rs" ],
+                "licenses": [ "root//foo/LICENSE" ],
+                "preferred_linkage": "static",
+                "visibility": []
+            }
+        }"#;
+
+        let rules = from_bytes(input.as_bytes(), &Cells::default())?;
+        assert_eq!(rules.len(), 2);
+
+        let bin = &rules[&target("//foo:bin")];
+        assert_eq!(bin.typ.name(), "rust_binary");
+        assert_eq!(bin.base_path, PathBuf::from("foo"));
+        assert_eq!(bin.common.deps, vec![target("//foo:lib")]);
+        assert_eq!(bin.direct_dependencies, vec![target("//foo:lib")]);
+        assert_eq!(bin.typ.krate(), Some("bin"));
+        assert_eq!(bin.typ.crate_root(), Some(Path::new("src/main.rs")));
+
+        let lib = &rules[&target("//foo:lib")];
+        assert_eq!(lib.typ.name(), "rust_library");
+        assert_eq!(lib.typ.krate(), Some("mylib"));
+        assert_eq!(lib.typ.crate_root(), Some(Path::new("src/lib.rs")));
+        assert_eq!(lib.typ.licenses(), ["LICENSE"]);
+
+        Ok(())
+    }
+}
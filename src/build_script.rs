@@ -0,0 +1,166 @@
+//! Model of the generated build scripts.
+//!
+//! Some rule attributes can't be expressed in Cargo.toml, but can be passed to
+//! rustc by a build script printing `cargo:` instructions. Such build scripts
+//! are generated next to the manifests. Similarly to the manifests, existing
+//! build scripts are only updated if they are marked with `GENERATED_MARKER`,
+//! since hand-written ones can't be merged with the generated instructions.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::rustc_flags::Cfg;
+
+/// Comment marking build scripts generated by the translation.
+pub const GENERATED_MARKER: &str = "// @generated by transantlator";
+
+/// Path to the build script, relative to the package directory.
+pub const FILE_NAME: &str = "build.rs";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BuildScript {
+    /// Linker arguments for every binary and test of the package.
+    pub link_args: Vec<String>,
+    /// Linker arguments for every binary of the package.
+    pub bins_link_args: Vec<String>,
+    /// Linker arguments for single binaries, keyed by their names.
+    pub bin_link_args: BTreeMap<String, Vec<String>>,
+    /// Linker arguments for every test of the package.
+    pub tests_link_args: Vec<String>,
+    /// Configuration options set for every target of the package. They are
+    /// declared as expected, so that rustc doesn't warn about them.
+    pub cfgs: Vec<Cfg>,
+}
+
+impl BuildScript {
+    pub fn is_empty(&self) -> bool {
+        self.instructions().is_empty()
+    }
+
+    /// Returns the `cargo:` instructions, in the order they are printed.
+    pub fn instructions(&self) -> Vec<String> {
+        let mut instructions = vec![];
+        let mut push = |key: &str, values: &[String]| {
+            for value in values {
+                instructions.push(format!("cargo:{}={}", key, value));
+            }
+        };
+
+        push("rustc-link-arg", &self.link_args);
+        push("rustc-link-arg-bins", &self.bins_link_args);
+        for (bin, args) in &self.bin_link_args {
+            let args = args.iter().map(|arg| format!("{}={}", bin, arg));
+            push("rustc-link-arg-bin", &args.collect::<Vec<_>>());
+        }
+        push("rustc-link-arg-tests", &self.tests_link_args);
+
+        let cfgs = self.cfgs.iter().map(ToString::to_string);
+        push("rustc-cfg", &cfgs.collect::<Vec<_>>());
+        let mut expected = BTreeMap::<_, Vec<_>>::new();
+        for cfg in &self.cfgs {
+            expected.entry(&cfg.name).or_default().push(&cfg.value);
+        }
+        for (name, values) in expected {
+            // Cargo 1.77 to 1.79 only accept check-cfg with the `cargo::` prefix
+            if values == [&None] {
+                instructions.push(format!("cargo::rustc-check-cfg=cfg({})", name));
+                continue;
+            }
+            let values = values.iter().map(|value| match value {
+                Some(value) => format!("{:?}", value),
+                None => String::from("none()"),
+            });
+            instructions.push(format!(
+                "cargo::rustc-check-cfg=cfg({}, values({}))",
+                name,
+                values.collect::<Vec<_>>().join(", ")
+            ));
+        }
+
+        instructions
+    }
+}
+
+impl fmt::Display for BuildScript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{}", GENERATED_MARKER)?;
+        writeln!(f)?;
+        writeln!(f, "fn main() {{")?;
+        for instruction in self.instructions() {
+            writeln!(f, "    println!(\"{}\");", format_str(&instruction))?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+/// Escapes the string, so that it's printed as is by `println!`.
+fn format_str(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '{' => escaped.push_str("{{"),
+            '}' => escaped.push_str("}}"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn print_instructions() {
+        let script = BuildScript {
+            bins_link_args: vec![String::from("-Wl,--gc-sections")],
+            bin_link_args: vec![(
+                String::from("tool"),
+                vec![String::from(r#"-Wl,--defsym={"x"}=0"#)],
+            )]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            script.to_string(),
+            r#"// @generated by transantlator
+
+fn main() {
+    println!("cargo:rustc-link-arg-bins=-Wl,--gc-sections");
+    println!("cargo:rustc-link-arg-bin=tool=-Wl,--defsym={{\"x\"}}=0");
+}
+"#
+        );
+        assert!(BuildScript::default().is_empty());
+    }
+
+    #[test]
+    fn declare_cfgs() {
+        let script = BuildScript {
+            cfgs: ["foo", "key=\"a\"", "key=\"b\"", "bar", "bar=\"x\""]
+                .iter()
+                .map(|cfg| Cfg::parse(cfg))
+                .collect(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            script.instructions(),
+            [
+                "cargo:rustc-cfg=foo",
+                "cargo:rustc-cfg=key=\"a\"",
+                "cargo:rustc-cfg=key=\"b\"",
+                "cargo:rustc-cfg=bar",
+                "cargo:rustc-cfg=bar=\"x\"",
+                "cargo::rustc-check-cfg=cfg(bar, values(none(), \"x\"))",
+                "cargo::rustc-check-cfg=cfg(foo)",
+                "cargo::rustc-check-cfg=cfg(key, values(\"a\", \"b\"))",
+            ]
+        );
+    }
+}
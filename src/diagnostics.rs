@@ -0,0 +1,208 @@
+//! Diagnostics about the translated rules.
+//!
+//! Problems with individual rules (e.g. conflicting features of rules sharing a
+//! package) don't stop the translation right away. Instead, they are collected
+//! across all buildfiles and reported together at the end, either in a human
+//! readable form or as JSON, one diagnostic per line.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::buck::BuildTarget;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    /// Prevents the manifests from being generated.
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Identifies the kind of the diagnostic, e.g. `conflicting-features`.
+    pub code: &'static str,
+    pub message: String,
+    /// Offending build target, if the diagnostic concerns a single one.
+    pub target: Option<BuildTarget>,
+    /// Directory of the buildfile (or of the package) the diagnostic concerns.
+    pub base_path: Option<PathBuf>,
+    /// How the problem can be fixed.
+    pub suggestion: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &'static str, message: impl Into<String>) -> Self {
+        Diagnostic::new(Severity::Error, code, message)
+    }
+
+    pub fn warning(code: &'static str, message: impl Into<String>) -> Self {
+        Diagnostic::new(Severity::Warning, code, message)
+    }
+
+    fn new(severity: Severity, code: &'static str, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity,
+            code,
+            message: message.into(),
+            target: None,
+            base_path: None,
+            suggestion: None,
+        }
+    }
+
+    pub fn with_target(self, target: &BuildTarget, base_path: &Path) -> Self {
+        Diagnostic {
+            target: Some(target.clone()),
+            ..self.with_base_path(base_path)
+        }
+    }
+
+    pub fn with_base_path(self, base_path: &Path) -> Self {
+        Diagnostic {
+            base_path: Some(base_path.to_owned()),
+            ..self
+        }
+    }
+
+    pub fn with_suggestion(self, suggestion: impl Into<String>) -> Self {
+        Diagnostic {
+            suggestion: Some(suggestion.into()),
+            ..self
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}[{}]: {}", self.severity, self.code, self.message)?;
+        match (&self.target, &self.base_path) {
+            (Some(target), _) => write!(f, "\n  --> {}", target)?,
+            (None, Some(base_path)) => write!(f, "\n  --> {}", base_path.display())?,
+            (None, None) => {}
+        }
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, "\n  = help: {}", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+/// How diagnostics are printed, selected with `--message-format`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MessageFormat {
+    #[default]
+    Human,
+    Json,
+}
+
+impl FromStr for MessageFormat {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(MessageFormat::Human),
+            "json" => Ok(MessageFormat::Json),
+            _ => Err(failure::format_err!("Unknown message format {}", s)),
+        }
+    }
+}
+
+/// Diagnostics collected during the translation.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.0.push(diagnostic);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.0.iter()
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+            .count()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.error_count() > 0
+    }
+
+    /// Prints the diagnostics to stderr or, as JSON, to stdout.
+    pub fn emit(&self, format: MessageFormat) -> serde_json::Result<()> {
+        for diagnostic in self.iter() {
+            match format {
+                MessageFormat::Human => eprintln!("{}\n", diagnostic),
+                MessageFormat::Json => println!("{}", serde_json::to_string(diagnostic)?),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Extend<Diagnostic> for Diagnostics {
+    fn extend<I: IntoIterator<Item = Diagnostic>>(&mut self, iter: I) {
+        self.0.extend(iter);
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, diagnostic) in self.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_diagnostics() -> Result<(), failure::Error> {
+        let target = "//foo:bar".parse()?;
+        let mut diagnostics = Diagnostics::default();
+        diagnostics.push(
+            Diagnostic::error("conflicting-features", "Rules enable different features")
+                .with_target(&target, Path::new("foo"))
+                .with_suggestion("Enable the same features"),
+        );
+        diagnostics.push(Diagnostic::warning("other", "Something").with_base_path(Path::new("")));
+
+        assert_eq!(diagnostics.error_count(), 1);
+        assert_eq!(
+            diagnostics.to_string(),
+            "error[conflicting-features]: Rules enable different features\n  \
+             --> //foo:bar\n  \
+             = help: Enable the same features\n\
+             warning[other]: Something\n  \
+             --> "
+        );
+        assert_eq!(
+            serde_json::to_string(&diagnostics.0[0])?,
+            r#"{"severity":"error","code":"conflicting-features","message":"Rules enable different features","target":"//foo:bar","base_path":"foo","suggestion":"Enable the same features"}"#
+        );
+
+        Ok(())
+    }
+}
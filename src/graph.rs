@@ -9,13 +9,13 @@ use crate::buck::{BuildTarget, Rules};
 
 pub type DepGraph<'a> = DiGraphMap<&'a BuildTarget, ()>;
 
-pub fn dep_graph(rules: &Rules) -> DepGraph {
+pub fn dep_graph(rules: &Rules) -> DepGraph<'_> {
     let mut graph = DepGraph::new();
 
     for (target, rule) in rules {
         graph.add_node(target);
         for dep in &rule.common.deps {
-            graph.add_edge(target, &dep, ());
+            graph.add_edge(target, dep, ());
         }
     }
 
@@ -26,6 +26,7 @@ pub fn output_graphviz(filename: &Path, graph: &DepGraph<'_>) -> std::io::Result
     let mut file = OpenOptions::new()
         .write(true)
         .create(true)
+        .truncate(true)
         .open(filename)?;
     let output = format!("{:?}", Dot::with_config(&graph, &[Config::EdgeNoLabel]));
 
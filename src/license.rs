@@ -0,0 +1,247 @@
+//! Identification of well-known license texts.
+//!
+//! Buck only tracks license files, whereas Cargo prefers an SPDX license
+//! expression in the `license` field and falls back to `license-file` only for
+//! nonstandard licenses.
+
+use std::collections::BTreeSet;
+
+/// Distinctive phrases of well-known licenses, along with their SPDX
+/// identifiers. More specific licenses need to come before the ones they could
+/// be mistaken for (e.g. LGPL before GPL). Note that the GPL texts themselves
+/// mention the LGPL, so it's only matched by phrases unique to its texts.
+///
+/// The texts don't tell whether later versions of the GNU licenses apply too,
+/// which is stated in the source files instead, so the `-only` variants are
+/// assumed.
+const KNOWN_LICENSES: &[(&str, &[&str])] = &[
+    (
+        "Apache-2.0",
+        &["apache license", "version 2.0, january 2004"],
+    ),
+    (
+        "MIT",
+        &[
+            "permission is hereby granted, free of charge, to any person obtaining a copy",
+            "the above copyright notice and this permission notice shall be included",
+        ],
+    ),
+    (
+        "BSD-4-Clause",
+        &[
+            "redistribution and use in source and binary forms",
+            "all advertising materials mentioning features or use of this software",
+        ],
+    ),
+    (
+        "BSD-3-Clause",
+        &[
+            "redistribution and use in source and binary forms",
+            "neither the name of",
+        ],
+    ),
+    (
+        "BSD-2-Clause",
+        &["redistribution and use in source and binary forms"],
+    ),
+    (
+        "ISC",
+        &["permission to use, copy, modify, and/or distribute this software for any purpose"],
+    ),
+    ("MPL-2.0", &["mozilla public license version 2.0"]),
+    (
+        "LGPL-3.0-only",
+        &["this version of the gnu lesser general public license incorporates"],
+    ),
+    (
+        "LGPL-2.1-only",
+        &[
+            "gnu lesser general public license",
+            "version 2.1, february 1999",
+        ],
+    ),
+    (
+        "GPL-3.0-only",
+        &["gnu general public license", "version 3, 29 june 2007"],
+    ),
+    (
+        "GPL-2.0-only",
+        &["gnu general public license", "version 2, june 1991"],
+    ),
+    ("BSL-1.0", &["boost software license - version 1.0"]),
+    (
+        "Unlicense",
+        &["this is free and unencumbered software released into the public domain"],
+    ),
+    (
+        "Zlib",
+        &[
+            "this software is provided 'as-is', without any express or implied warranty",
+            "altered source versions must be plainly marked as such",
+        ],
+    ),
+];
+
+/// Returns the SPDX identifier of the license, if the text is recognized as a
+/// well-known one.
+pub fn identify(text: &str) -> Option<&'static str> {
+    // Ignore case and formatting differences, e.g. due to line wrapping
+    let text = text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+
+    KNOWN_LICENSES
+        .iter()
+        .find(|(_, phrases)| phrases.iter().all(|phrase| text.contains(phrase)))
+        .map(|(id, _)| *id)
+}
+
+/// Returns the SPDX expression of the package with the well-known licenses.
+///
+/// Several license files usually let the user choose one of the licenses, but
+/// may also require all of them, e.g. for bundled code. So apart from the
+/// common dual MIT and Apache-2.0 licensing, they're considered ambiguous and
+/// `None` is returned.
+pub fn expression(ids: &BTreeSet<&str>) -> Option<String> {
+    match ids.iter().collect::<Vec<_>>().as_slice() {
+        [id] => Some(id.to_string()),
+        [&"Apache-2.0", &"MIT"] => Some(String::from("MIT OR Apache-2.0")),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identify_mit() {
+        let text = r#"MIT License
+
+Copyright (c) 2018 Example
+
+Permission is hereby granted, free of charge, to any person obtaining a copy
+of this software and associated documentation files (the "Software"), to deal
+in the Software without restriction, including without limitation the rights
+to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+copies of the Software, and to permit persons to whom the Software is
+furnished to do so, subject to the following conditions:
+
+The above copyright notice and this permission notice shall be included in all
+copies or substantial portions of the Software.
+"#;
+        assert_eq!(identify(text), Some("MIT"));
+    }
+
+    #[test]
+    fn identify_apache() {
+        let text = r#"
+                                 Apache License
+                           Version 2.0, January 2004
+                        http://www.apache.org/licenses/
+"#;
+        assert_eq!(identify(text), Some("Apache-2.0"));
+    }
+
+    #[test]
+    fn identify_gpl_variants() {
+        let gpl = "GNU GENERAL PUBLIC LICENSE\n   Version 3, 29 June 2007";
+        assert_eq!(identify(gpl), Some("GPL-3.0-only"));
+
+        // Excerpts of the texts, which mention the other GNU licenses
+        let texts = [
+            (
+                "GPL-2.0-only",
+                r#"
+                    GNU GENERAL PUBLIC LICENSE
+                       Version 2, June 1991
+
+consider it more useful to permit linking proprietary applications with the
+library.  If this is what you want to do, use the GNU Lesser General
+Public License instead of this License.
+"#,
+            ),
+            (
+                "GPL-3.0-only",
+                r#"
+                    GNU GENERAL PUBLIC LICENSE
+                       Version 3, 29 June 2007
+
+the library.  If this is what you want to do, use the GNU Lesser General
+Public License instead of this License.  But first, please read
+<https://www.gnu.org/licenses/why-not-lgpl.html>.
+"#,
+            ),
+            (
+                "LGPL-2.1-only",
+                r#"
+                  GNU LESSER GENERAL PUBLIC LICENSE
+                       Version 2.1, February 1999
+
+  Most GNU software, including some libraries, is covered by the
+ordinary GNU General Public License.  This license, the GNU Lesser
+General Public License, applies to certain designated libraries, and
+"#,
+            ),
+            (
+                "LGPL-3.0-only",
+                r#"
+                   GNU LESSER GENERAL PUBLIC LICENSE
+                       Version 3, 29 June 2007
+
+  This version of the GNU Lesser General Public License incorporates
+the terms and conditions of version 3 of the GNU General Public
+License, supplemented by the additional permissions listed below.
+"#,
+            ),
+        ];
+        for (id, text) in &texts {
+            assert_eq!(identify(text), Some(*id));
+        }
+    }
+
+    #[test]
+    fn identify_bsd_variants() {
+        let bsd3 = r#"
+Redistribution and use in source and binary forms, with or without
+modification, are permitted provided that the following conditions are met:
+...
+3. Neither the name of the copyright holder nor the names of its
+   contributors may be used to endorse or promote products derived from
+   this software without specific prior written permission.
+"#;
+        assert_eq!(identify(bsd3), Some("BSD-3-Clause"));
+
+        let bsd4 = r#"
+Redistribution and use in source and binary forms, with or without
+modification, are permitted provided that the following conditions are met:
+...
+3. All advertising materials mentioning features or use of this software
+   must display the following acknowledgement:
+   This product includes software developed by the organization.
+4. Neither the name of the copyright holder nor the names of its
+   contributors may be used to endorse or promote products derived from
+   this software without specific prior written permission.
+"#;
+        assert_eq!(identify(bsd4), Some("BSD-4-Clause"));
+    }
+
+    #[test]
+    fn dual_licenses() {
+        let ids = |ids: &[&'static str]| ids.iter().copied().collect::<BTreeSet<_>>();
+        assert_eq!(expression(&ids(&["MIT"])), Some(String::from("MIT")));
+        assert_eq!(
+            expression(&ids(&["MIT", "Apache-2.0"])),
+            Some(String::from("MIT OR Apache-2.0"))
+        );
+        assert_eq!(expression(&ids(&["MIT", "BSD-3-Clause"])), None);
+    }
+
+    #[test]
+    fn identify_unknown() {
+        assert_eq!(identify("All rights reserved."), None);
+        assert_eq!(identify(""), None);
+    }
+}
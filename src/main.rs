@@ -8,41 +8,247 @@ use std::path::{Path, PathBuf};
 
 use getopts::Options;
 
+use crate::buck::BuildTargetPattern;
+use crate::diagnostics::MessageFormat;
+use crate::query::{Frontend, QueryBackend};
+
+mod bazel;
 mod buck;
+mod buck2;
+mod build_script;
+mod diagnostics;
 mod graph;
+mod license;
+mod manifest;
+mod query;
+mod rustc_flags;
+mod target;
 mod translate;
 
 fn main() -> Result<(), failure::Error> {
     let args = std::env::args().collect::<Vec<_>>();
 
     let mut opts = Options::new();
-    opts.reqopt("d", "dir", "Directory to run inside", "DIR");
-    opts.reqopt("r", "rule", "Buck rule to translate", "RULE");
+    opts.optopt("d", "dir", "Directory to run inside", "DIR");
+    opts.optmulti(
+        "r",
+        "rule",
+        "Buck rule (or target pattern) to translate, can be repeated",
+        "RULE",
+    );
+    opts.optopt(
+        "",
+        "root",
+        "Buck root, instead of the one reported by Buck",
+        "ROOT",
+    );
+    opts.optopt(
+        "",
+        "from-json",
+        "Read Buck query output from a file (or stdin for -) instead of running Buck",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "save-json",
+        "Save raw Buck query output to a file",
+        "FILE",
+    );
+    opts.optopt(
+        "",
+        "frontend",
+        "Build system to query: buck, buck2 or bazel (detected by default)",
+        "NAME",
+    );
+    opts.optflag(
+        "",
+        "configured",
+        "Query configured targets (with buck2 cquery)",
+    );
     opts.optopt("", "gv", "Graphviz file to output Buck rule graph", "DOT");
+    opts.optflag(
+        "",
+        "dry-run",
+        "Print a diff of the manifests instead of writing them",
+    );
+    opts.optflag(
+        "",
+        "check",
+        "Like --dry-run but exit with an error if any manifest would change",
+    );
+    opts.optopt(
+        "o",
+        "out-dir",
+        "Generate the Cargo tree in a separate directory, mirroring the Buck tree",
+        "DIR",
+    );
+    opts.optmulti(
+        "",
+        "include",
+        "Only translate rules matching the target pattern",
+        "PATTERN",
+    );
+    opts.optmulti(
+        "",
+        "exclude",
+        "Don't translate rules matching the target pattern",
+        "PATTERN",
+    );
+    opts.optflag(
+        "k",
+        "keep-going",
+        "Skip unsupported rules instead of failing",
+    );
+    opts.optflag(
+        "",
+        "split-cycles",
+        "Move binaries causing dependency cycles between packages into separate packages",
+    );
+    opts.optopt(
+        "",
+        "message-format",
+        "Print diagnostics in the given format (human or json)",
+        "FMT",
+    );
+    opts.optflag("v", "verbose", "Print queried Buck rules");
     let matches = opts.parse(&args[1..])?;
-    let dir = PathBuf::from(matches.opt_str("d").unwrap());
-    let rule = matches.opt_str("r").unwrap();
+    let message_format = matches
+        .opt_str("message-format")
+        .map(|format| format.parse::<MessageFormat>())
+        .transpose()?
+        .unwrap_or_default();
+    let dir = PathBuf::from(matches.opt_str("d").unwrap_or_else(|| String::from(".")));
+
+    let parse_patterns = |name| {
+        matches
+            .opt_strs(name)
+            .iter()
+            .map(|pattern| pattern.parse::<BuildTargetPattern>())
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let rules = parse_patterns("rule")?;
+    let filter = translate::Filter {
+        include: parse_patterns("include")?,
+        exclude: parse_patterns("exclude")?,
+    };
 
-    let root = buck::buck_root(dir)?;
-    let rules = buck::query_rules(&root, rule)?;
+    let frontend = matches
+        .opt_str("frontend")
+        .map(|frontend| frontend.parse::<Frontend>());
 
-    println!("{:#?}", rules);
-    println!("root: {:#?}", root);
+    let (backend, requested): (Box<dyn QueryBackend>, _) = match matches.opt_str("from-json") {
+        Some(path) => {
+            let root = matches
+                .opt_str("root")
+                .ok_or_else(|| failure::format_err!("--from-json requires --root"))?;
+            let frontend = frontend.unwrap_or_else(|| Ok(Frontend::detect(Path::new(&root))))?;
+            let backend = query::Recorded::open(root, frontend, path)?;
+            let requested = if rules.is_empty() {
+                let roots = backend.roots().into_iter();
+                roots.map(BuildTargetPattern::Target).collect()
+            } else {
+                rules
+            };
+            (Box::new(backend), requested)
+        }
+        None => {
+            if rules.is_empty() {
+                return Err(failure::format_err!(
+                    "--rule is required when querying Buck"
+                ));
+            }
+            let frontend = frontend.unwrap_or_else(|| Ok(Frontend::detect(&dir)))?;
+            let backend = cli_backend(
+                frontend,
+                dir,
+                matches.opt_str("root"),
+                matches.opt_str("save-json"),
+                matches.opt_present("configured"),
+            );
+            (backend, rules)
+        }
+    };
+
+    let out_dir = matches.opt_str("out-dir").map(PathBuf::from);
+    if let Some(out_dir) = &out_dir {
+        std::fs::create_dir_all(out_dir)?;
+    }
+
+    let translate::Translation {
+        buck_root: root,
+        rules,
+        files,
+        mut diagnostics,
+    } = translate::translate_rules(
+        &*backend,
+        out_dir.as_deref(),
+        &requested,
+        &filter,
+        matches.opt_present("split-cycles"),
+    )?;
+
+    if matches.opt_present("v") {
+        println!("{:#?}", rules);
+        println!("root: {:#?}", root);
+    }
 
     if let Some(gv_filename) = matches.opt_str("gv") {
         let dep_graph = graph::dep_graph(&rules);
         graph::output_graphviz(Path::new(&gv_filename), &dep_graph)?;
     }
 
-    if let Some((target, rule)) = rules.iter().find(|(_, r)| !r.typ.is_supported()) {
+    let keep_going = matches.opt_present("keep-going");
+    diagnostics.extend(translate::check_unsupported_rules(&rules, keep_going));
+    diagnostics.emit(message_format)?;
+    if diagnostics.has_errors() {
         return Err(failure::format_err!(
-            "Build target {} (of type {}) not supported",
-            target,
-            rule.typ.name()
+            "Couldn't translate the rules due to {} error(s)",
+            diagnostics.error_count()
         ));
     }
 
-    translate::translate_rules(&root, rules.iter())?;
+    let out_root = out_dir.as_deref().unwrap_or(&root);
+
+    if matches.opt_present("dry-run") || matches.opt_present("check") {
+        for file in &files {
+            print!("{}", file.diff());
+        }
+        if matches.opt_present("check") && files.iter().any(|f| f.is_changed()) {
+            std::process::exit(1);
+        }
+    } else {
+        for file in files.iter().filter(|f| f.is_changed()) {
+            file.write(out_root)?;
+        }
+    }
 
     Ok(())
 }
+
+/// Returns a backend running the CLI of the given build system.
+fn cli_backend(
+    frontend: Frontend,
+    dir: PathBuf,
+    root: Option<String>,
+    save_json: Option<String>,
+    configured: bool,
+) -> Box<dyn QueryBackend> {
+    macro_rules! configure {
+        ($backend:expr) => {{
+            let mut backend = $backend;
+            if let Some(root) = root {
+                backend = backend.with_root(root);
+            }
+            if let Some(path) = save_json {
+                backend = backend.save_json(path);
+            }
+            Box::new(backend)
+        }};
+    }
+
+    match frontend {
+        Frontend::Buck => configure!(buck::BuckCli::new(dir)),
+        Frontend::Buck2 => configure!(buck2::Buck2Cli::new(dir).configured(configured)),
+        Frontend::Bazel => configure!(bazel::BazelCli::new(dir)),
+    }
+}
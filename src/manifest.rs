@@ -0,0 +1,1016 @@
+//! Typed model of the generated Cargo.toml manifests.
+//!
+//! Translation fills in a `Manifest`, which is then serialized via `toml_edit`.
+//! This takes care of proper escaping and keeps the sections and their keys in
+//! a stable order, regardless of the order in which they were filled in.
+//!
+//! Manifests are merged into the existing Cargo.toml files, rather than
+//! overwriting them. Only the keys that are translated from Buck are updated,
+//! which preserves formatting, comments and other sections, such as
+//! [package.metadata]. Manifests created from scratch are marked with
+//! `GENERATED_MARKER` and are considered to be fully owned by the translation,
+//! so the translated keys that are no longer generated, including [profile]
+//! settings, are removed from them.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use toml_edit::{
+    value, Array, ArrayOfTables, DocumentMut, InlineTable, Item, Table, TableLike, Value,
+};
+
+use crate::build_script::BuildScript;
+use crate::rustc_flags::LintLevel;
+
+/// Comment marking manifests generated from scratch.
+pub const GENERATED_MARKER: &str = "# @generated by transantlator";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Manifest {
+    pub package: Option<Package>,
+    pub lib: Option<Target>,
+    pub bins: Vec<Target>,
+    pub tests: Vec<Target>,
+    pub dependencies: BTreeMap<String, Dependency>,
+    pub dev_dependencies: BTreeMap<String, Dependency>,
+    /// Features, along with the features they enable. The `default` feature
+    /// is always written first.
+    pub features: BTreeMap<String, Vec<String>>,
+    /// Lints, keyed by the tool (e.g. `rust` or `clippy`) and their name.
+    pub lints: BTreeMap<String, BTreeMap<String, Lint>>,
+    pub workspace: Option<Workspace>,
+    /// Profiles (e.g. `dev` or `release`), which only take effect in the
+    /// workspace root manifest.
+    pub profiles: BTreeMap<String, Profile>,
+    /// Build script generated along with the manifest, which is referred to
+    /// by `package.build`.
+    pub build_script: Option<BuildScript>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    pub authors: Vec<String>,
+    /// SPDX license expression.
+    pub license: Option<String>,
+    /// Path to a nonstandard license file, relative to the package directory.
+    pub license_file: Option<PathBuf>,
+    /// Path to the build script, relative to the package directory.
+    pub build: Option<PathBuf>,
+}
+
+impl Package {
+    pub fn new(name: impl Into<String>) -> Package {
+        Package {
+            name: name.into(),
+            version: String::from("0.1.0"),
+            authors: vec![String::from("Example <author@example.com>")],
+            license: None,
+            license_file: None,
+            build: None,
+        }
+    }
+}
+
+/// A single [lib], [[bin]] or [[test]] target.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub name: String,
+    /// Path to the crate root, relative to the package directory.
+    pub path: PathBuf,
+    /// Whether to use the standard test harness. Defaults to `true`.
+    pub harness: Option<bool>,
+    /// Whether the library is a procedural macro. Defaults to `false`.
+    pub proc_macro: Option<bool>,
+}
+
+impl Target {
+    pub fn new(name: impl Into<String>, path: impl Into<PathBuf>) -> Target {
+        Target {
+            name: name.into(),
+            path: path.into(),
+            harness: None,
+            proc_macro: None,
+        }
+    }
+
+    fn to_table(&self) -> Table {
+        let mut table = Table::new();
+        table["name"] = value(&self.name);
+        table["path"] = value(path_str(&self.path));
+        if let Some(harness) = self.harness {
+            table["harness"] = value(harness);
+        }
+        if let Some(proc_macro) = self.proc_macro {
+            table["proc-macro"] = value(proc_macro);
+        }
+        table
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dependency {
+    /// Path to the dependency package, relative to the package directory.
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lint {
+    pub level: LintLevel,
+    /// Lints are applied in the order of their priorities, so that e.g. a lint
+    /// group can be overridden by a single lint from it. Defaults to 0.
+    pub priority: i32,
+}
+
+impl Lint {
+    fn to_value(&self) -> Item {
+        if self.priority == 0 {
+            return value(self.level.as_str());
+        }
+        let mut inline = InlineTable::new();
+        inline.insert("level", self.level.as_str().into());
+        inline.insert("priority", i64::from(self.priority).into());
+        value(inline)
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Profile {
+    /// Settings of the whole workspace, e.g. `opt-level = 3`.
+    pub settings: BTreeMap<String, ProfileValue>,
+    /// Settings overridden for single packages, keyed by their names.
+    pub packages: BTreeMap<String, BTreeMap<String, ProfileValue>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProfileValue {
+    Bool(bool),
+    Integer(i64),
+    String(String),
+}
+
+impl ProfileValue {
+    fn to_value(&self) -> Item {
+        match self {
+            ProfileValue::Bool(b) => value(*b),
+            ProfileValue::Integer(i) => value(*i),
+            ProfileValue::String(s) => value(s),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Workspace {
+    /// Member package directories, relative to the workspace root.
+    pub members: Vec<PathBuf>,
+    pub default_members: Vec<PathBuf>,
+}
+
+impl Manifest {
+    pub fn to_document(&self) -> DocumentMut {
+        let mut doc = DocumentMut::new();
+
+        if let Some(package) = &self.package {
+            let mut table = Table::new();
+            table["name"] = value(&package.name);
+            table["version"] = value(&package.version);
+            table["authors"] = value(package.authors.iter().collect::<Array>());
+            if let Some(license) = &package.license {
+                table["license"] = value(license);
+            }
+            if let Some(license_file) = &package.license_file {
+                table["license-file"] = value(path_str(license_file));
+            }
+            if let Some(build) = &package.build {
+                table["build"] = value(path_str(build));
+            }
+            doc["package"] = Item::Table(table);
+        }
+
+        if let Some(lib) = &self.lib {
+            doc["lib"] = Item::Table(lib.to_table());
+        }
+        for (key, targets) in &[("bin", &self.bins), ("test", &self.tests)] {
+            if !targets.is_empty() {
+                let tables = targets.iter().map(Target::to_table).collect();
+                doc[key] = Item::ArrayOfTables(tables);
+            }
+        }
+
+        for (key, deps) in &[
+            ("dependencies", &self.dependencies),
+            ("dev-dependencies", &self.dev_dependencies),
+        ] {
+            if !deps.is_empty() {
+                let mut table = Table::new();
+                for (name, dep) in deps.iter() {
+                    let mut inline = InlineTable::new();
+                    inline.insert("path", path_str(&dep.path).into());
+                    table[name] = value(inline);
+                }
+                doc[key] = Item::Table(table);
+            }
+        }
+
+        if !self.features.is_empty() {
+            let mut table = Table::new();
+            let default = self.features.get_key_value("default");
+            let others = self.features.iter().filter(|(name, _)| *name != "default");
+            for (name, enables) in default.into_iter().chain(others) {
+                table[name] = value(enables.iter().collect::<Array>());
+            }
+            doc["features"] = Item::Table(table);
+        }
+
+        if !self.lints.is_empty() {
+            let mut table = Table::new();
+            table.set_implicit(true);
+            for (tool, lints) in &self.lints {
+                let mut tool_table = Table::new();
+                for (name, lint) in lints {
+                    tool_table[name] = lint.to_value();
+                }
+                table[tool] = Item::Table(tool_table);
+            }
+            doc["lints"] = Item::Table(table);
+        }
+
+        if let Some(workspace) = &self.workspace {
+            let mut table = Table::new();
+            table["members"] = value(multiline_paths(&workspace.members));
+            if !workspace.default_members.is_empty() {
+                table["default-members"] = value(multiline_paths(&workspace.default_members));
+            }
+            doc["workspace"] = Item::Table(table);
+        }
+
+        if !self.profiles.is_empty() {
+            let settings_table = |settings: &BTreeMap<String, ProfileValue>| {
+                let mut table = Table::new();
+                table.set_implicit(true);
+                for (key, value) in settings {
+                    table[key] = value.to_value();
+                }
+                table
+            };
+
+            let mut table = Table::new();
+            table.set_implicit(true);
+            for (name, profile) in &self.profiles {
+                let mut profile_table = settings_table(&profile.settings);
+                if !profile.packages.is_empty() {
+                    let mut packages = Table::new();
+                    packages.set_implicit(true);
+                    for (package, settings) in &profile.packages {
+                        packages[package] = Item::Table(settings_table(settings));
+                    }
+                    profile_table["package"] = Item::Table(packages);
+                }
+                table[name] = Item::Table(profile_table);
+            }
+            doc["profile"] = Item::Table(table);
+        }
+
+        doc
+    }
+}
+
+impl Manifest {
+    /// Merges the manifest into the contents of an existing Cargo.toml file
+    /// and returns the updated contents.
+    pub fn merge_into(&self, existing: &str) -> Result<String, toml_edit::TomlError> {
+        if existing.trim().is_empty() {
+            return Ok(format!("{}\n\n{}", GENERATED_MARKER, self));
+        }
+
+        let mut doc = existing.parse::<DocumentMut>()?;
+        // Prune stale keys only from the manifests that we fully own
+        let prune = is_generated(existing);
+        let new = self.to_document();
+
+        if let Some(new_package) = new.get("package").and_then(Item::as_table_like) {
+            let package = section(&mut doc, "package");
+            sync_key(package, new_package, "name", true);
+            // Don't override actual package metadata with the placeholders, nor
+            // add them to manifests maintained by hand
+            for key in &["version", "authors"] {
+                if prune && !package.contains_key(key) {
+                    sync_key(package, new_package, key, false);
+                }
+            }
+            for key in &["license", "license-file", "build"] {
+                sync_key(package, new_package, key, prune);
+            }
+        }
+
+        match new.get("lib").and_then(Item::as_table_like) {
+            Some(new_lib) => {
+                let lib = section(&mut doc, "lib");
+                sync_key(lib, new_lib, "name", true);
+                sync_key(lib, new_lib, "path", true);
+                sync_key(lib, new_lib, "proc-macro", prune);
+            }
+            None if prune => {
+                doc.remove("lib");
+            }
+            None => {}
+        }
+
+        for key in &["bin", "test"] {
+            let new_targets = new.get(key).and_then(Item::as_array_of_tables);
+            merge_targets(&mut doc, key, new_targets, prune);
+        }
+
+        for key in &["dependencies", "dev-dependencies", "features"] {
+            let new_table = new.get(key).and_then(Item::as_table_like);
+            if new_table.is_none() && !(prune && doc.contains_key(key)) {
+                continue;
+            }
+
+            let table = section(&mut doc, key);
+            let empty = Table::new();
+            let new_table = new_table.unwrap_or(&empty);
+            if prune {
+                remove_stale_keys(table, new_table);
+            }
+            for (name, item) in new_table.iter() {
+                match table.get_mut(name).and_then(Item::as_table_like_mut) {
+                    // Keep additional keys of dependencies, e.g. `features`
+                    Some(dep) if *key != "features" => {
+                        sync_key(dep, item.as_table_like().unwrap(), "path", true)
+                    }
+                    _ => set_item(table, name, item.clone()),
+                }
+            }
+            if table.is_empty() {
+                doc.remove(key);
+            }
+        }
+
+        merge_lints(
+            &mut doc,
+            new.get("lints").and_then(Item::as_table_like),
+            prune,
+        );
+
+        if let Some(new_workspace) = new.get("workspace").and_then(Item::as_table_like) {
+            let workspace = section(&mut doc, "workspace");
+            if prune {
+                sync_key(workspace, new_workspace, "members", true);
+            } else {
+                // Keep the members added by hand
+                let paths = |item: Option<&Item>| {
+                    let values = item.and_then(Item::as_array).into_iter().flatten();
+                    let paths = values.filter_map(Value::as_str).map(PathBuf::from);
+                    paths.collect::<Vec<_>>()
+                };
+                let mut members = paths(workspace.get("members"));
+                let new_members = paths(new_workspace.get("members"));
+                let missing = new_members.into_iter().filter(|m| !members.contains(m));
+                let missing = missing.collect::<Vec<_>>();
+                if !missing.is_empty() || !workspace.contains_key("members") {
+                    members.extend(missing);
+                    set_item(workspace, "members", value(multiline_paths(&members)));
+                }
+            }
+            sync_key(workspace, new_workspace, "default-members", prune);
+        }
+
+        // Profiles are often tuned by hand, so only the missing settings are
+        // added to hand-maintained manifests, see `conflicting_profile_settings`
+        match (new.get("profile"), doc.get_mut("profile")) {
+            (Some(new_profiles), Some(profiles)) if prune && profiles.is_table_like() => {
+                let profiles = profiles.as_table_like_mut().unwrap();
+                sync_tables(profiles, new_profiles.as_table_like().unwrap());
+            }
+            (Some(new_profiles), _) if prune => doc["profile"] = new_profiles.clone(),
+            (Some(new_profiles), _) => merge_missing(doc.as_table_mut(), "profile", new_profiles),
+            (None, _) if prune => {
+                doc.remove("profile");
+            }
+            (None, _) => {}
+        }
+
+        Ok(doc.to_string())
+    }
+}
+
+impl Manifest {
+    /// Returns the profile settings (e.g. `profile.release.lto`) which the
+    /// existing Cargo.toml sets to other values, so they aren't updated by
+    /// `merge_into`. Generated manifests have all of them updated.
+    pub fn conflicting_profile_settings(&self, existing: &str) -> Vec<String> {
+        let mut conflicts = vec![];
+        if is_generated(existing) {
+            return conflicts;
+        }
+        let doc = existing.parse::<DocumentMut>().unwrap_or_default();
+        let existing = doc.get("profile").and_then(Item::as_table);
+        if let (Some(existing), Some(new)) = (existing, self.to_document().get("profile")) {
+            let new = new.as_table().unwrap();
+            collect_conflicts(existing, new, "profile", &mut conflicts);
+        }
+        conflicts
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_document())
+    }
+}
+
+/// Returns whether the manifest is marked with `GENERATED_MARKER`.
+fn is_generated(manifest: &str) -> bool {
+    manifest.lines().any(|line| line.trim() == GENERATED_MARKER)
+}
+
+/// Returns the table-like section, creating an empty one if needed.
+fn section<'a>(doc: &'a mut DocumentMut, key: &str) -> &'a mut dyn TableLike {
+    if !doc.get(key).is_some_and(Item::is_table_like) {
+        doc[key] = Item::Table(Table::new());
+    }
+    doc[key].as_table_like_mut().unwrap()
+}
+
+/// Sets the key to the item, retaining formatting (e.g. trailing comments) of
+/// the previous value, if there was one.
+fn set_item(table: &mut dyn TableLike, key: &str, mut item: Item) {
+    if let (Some(Item::Value(old)), Item::Value(new)) = (table.get(key), &mut item) {
+        *new.decor_mut() = old.decor().clone();
+    }
+    table.insert(key, item);
+}
+
+/// Copies the key from the `new` table or, if it's missing there and `prune`
+/// is set, removes it.
+fn sync_key(table: &mut dyn TableLike, new: &dyn TableLike, key: &str, prune: bool) {
+    match new.get(key) {
+        Some(item) => set_item(table, key, item.clone()),
+        None if prune => {
+            table.remove(key);
+        }
+        None => {}
+    }
+}
+
+fn remove_stale_keys(table: &mut dyn TableLike, new: &dyn TableLike) {
+    let stale = table
+        .iter()
+        .map(|(key, _)| key.to_owned())
+        .filter(|key| !new.contains_key(key))
+        .collect::<Vec<_>>();
+    for key in stale {
+        table.remove(&key);
+    }
+}
+
+/// Makes the table match the `new` one, recursing into the tables of both, so
+/// that formatting of the values which are kept is retained.
+fn sync_tables(table: &mut dyn TableLike, new: &dyn TableLike) {
+    remove_stale_keys(table, new);
+    for (key, item) in new.iter() {
+        match (table.get_mut(key).and_then(Item::as_table_like_mut), item) {
+            (Some(table), Item::Table(new_table)) => sync_tables(table, new_table),
+            _ => set_item(table, key, item.clone()),
+        }
+    }
+}
+
+/// Merges [[bin]] or [[test]] targets, which are matched by their names.
+fn merge_targets(doc: &mut DocumentMut, key: &str, new: Option<&ArrayOfTables>, prune: bool) {
+    let name =
+        |target: &dyn TableLike| target.get("name").and_then(Item::as_str).map(str::to_owned);
+    let new_targets: Vec<_> = new.map(|new| new.iter().collect()).unwrap_or_default();
+    let new_names = new_targets.iter().map(|t| name(*t)).collect::<Vec<_>>();
+
+    // Targets written by hand may be an inline array of tables instead
+    if let Some(targets) = doc.get_mut(key).and_then(Item::as_array_mut) {
+        if prune {
+            // The first target isn't preceded by a space, unlike the others
+            let first_decor = targets.get(0).map(|target| target.decor().clone());
+            targets.retain(|target| {
+                let target = target.as_inline_table();
+                target.is_some_and(|target| new_names.contains(&name(target)))
+            });
+            if let (Some(first), Some(decor)) = (targets.get_mut(0), first_decor) {
+                *first.decor_mut() = decor;
+            }
+        }
+        for new_target in new_targets {
+            let target = targets
+                .iter_mut()
+                .filter_map(Value::as_inline_table_mut)
+                .find(|target| name(*target) == name(new_target));
+            match target {
+                Some(target) => sync_target(target, new_target, prune),
+                None => targets.push(new_target.clone().into_inline_table()),
+            }
+        }
+
+        if targets.is_empty() {
+            doc.remove(key);
+        }
+        return;
+    }
+
+    if !doc.get(key).is_some_and(Item::is_array_of_tables) {
+        if new_targets.is_empty() {
+            return;
+        }
+        doc[key] = Item::ArrayOfTables(ArrayOfTables::new());
+    }
+    let targets = doc[key].as_array_of_tables_mut().unwrap();
+
+    if prune {
+        targets.retain(|target| new_names.contains(&name(target)));
+    }
+    for new_target in new_targets {
+        let target = targets.iter_mut().find(|t| name(*t) == name(new_target));
+        match target {
+            Some(target) => sync_target(target, new_target, prune),
+            None => targets.push(new_target.clone()),
+        }
+    }
+
+    if targets.is_empty() {
+        doc.remove(key);
+    }
+}
+
+/// Updates the keys of the target which are translated, keeping the others.
+fn sync_target(target: &mut dyn TableLike, new: &Table, prune: bool) {
+    for key in &["path", "harness"] {
+        sync_key(target, new, key, prune);
+    }
+}
+
+/// Merges [lints.*] tables, unless the package inherits the workspace lints
+/// instead (which can't be combined with its own ones).
+fn merge_lints(doc: &mut DocumentMut, new: Option<&dyn TableLike>, prune: bool) {
+    let inherited = doc
+        .get("lints")
+        .and_then(Item::as_table_like)
+        .is_some_and(|lints| lints.contains_key("workspace"));
+    if inherited || (new.is_none() && !(prune && doc.contains_key("lints"))) {
+        return;
+    }
+
+    let empty = Table::new();
+    let new = new.unwrap_or(&empty);
+    if !doc.contains_key("lints") {
+        // Only the [lints.*] headers are written
+        let mut table = Table::new();
+        table.set_implicit(true);
+        doc["lints"] = Item::Table(table);
+    }
+    let lints = section(doc, "lints");
+    if prune {
+        remove_stale_keys(lints, new);
+    }
+    for (tool, new_tool) in new.iter() {
+        let new_tool = new_tool.as_table_like().unwrap();
+        if !lints.get(tool).is_some_and(Item::is_table_like) {
+            lints.insert(tool, Item::Table(Table::new()));
+        }
+        let tool_lints = lints
+            .get_mut(tool)
+            .and_then(Item::as_table_like_mut)
+            .unwrap();
+        if prune {
+            remove_stale_keys(tool_lints, new_tool);
+        }
+        for (name, item) in new_tool.iter() {
+            set_item(tool_lints, name, item.clone());
+        }
+    }
+
+    if lints.is_empty() {
+        doc.remove("lints");
+    }
+}
+
+/// Sets the key to the item, unless it's already set. Tables are merged with
+/// the existing ones recursively instead.
+fn merge_missing(table: &mut dyn TableLike, key: &str, item: &Item) {
+    let new_table = match item.as_table() {
+        Some(new_table) => new_table,
+        None if table.contains_key(key) => return,
+        None => return set_item(table, key, item.clone()),
+    };
+
+    if !table.get(key).is_some_and(Item::is_table_like) {
+        let mut empty = Table::new();
+        empty.set_implicit(true);
+        table.insert(key, Item::Table(empty));
+    }
+    let table = table
+        .get_mut(key)
+        .and_then(Item::as_table_like_mut)
+        .unwrap();
+    for (key, item) in new_table.iter() {
+        merge_missing(table, key, item);
+    }
+}
+
+/// Collects the dotted keys of the values which are set differently in the
+/// existing table.
+fn collect_conflicts(
+    table: &dyn TableLike,
+    new: &dyn TableLike,
+    prefix: &str,
+    conflicts: &mut Vec<String>,
+) {
+    let plain = |value: &Value| {
+        let mut value = value.clone();
+        value.decor_mut().clear();
+        value.to_string()
+    };
+
+    for (key, new_item) in new.iter() {
+        let path = format!("{}.{}", prefix, key);
+        match (table.get(key), new_item) {
+            (Some(Item::Table(existing)), Item::Table(new_table)) => {
+                collect_conflicts(existing, new_table, &path, conflicts)
+            }
+            (Some(Item::Value(existing)), Item::Value(new_value))
+                if plain(existing) == plain(new_value) => {}
+            (Some(_), _) => conflicts.push(path),
+            (None, _) => {}
+        }
+    }
+}
+
+/// Formats the path for the manifest, where the empty one means the current
+/// directory.
+fn path_str(path: &Path) -> String {
+    if path.as_os_str().is_empty() {
+        String::from(".")
+    } else {
+        path.display().to_string()
+    }
+}
+
+/// Formats the paths as an array with a single path per line.
+fn multiline_paths(paths: &[PathBuf]) -> Array {
+    let mut array = paths.iter().map(|path| path_str(path)).collect::<Array>();
+    for path in array.iter_mut() {
+        path.decor_mut().set_prefix("\n    ");
+    }
+    array.set_trailing("\n");
+    array.set_trailing_comma(true);
+    array
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_values() {
+        let manifest = Manifest {
+            package: Some(Package::new(r#"we"ird"#)),
+            lib: Some(Target::new("lib", r#"src/"quoted"\lib.rs"#)),
+            features: vec![(String::from("a.b"), vec![])].into_iter().collect(),
+            ..Default::default()
+        };
+        let output = manifest.to_string();
+        assert_eq!(
+            output,
+            r#"[package]
+name = 'we"ird'
+version = "0.1.0"
+authors = ["Example <author@example.com>"]
+
+[lib]
+name = "lib"
+path = 'src/"quoted"\lib.rs'
+
+[features]
+"a.b" = []
+"#
+        );
+        assert!(output.parse::<DocumentMut>().is_ok());
+    }
+
+    #[test]
+    fn stable_section_order() {
+        // Fill in the sections in reverse order
+        let mut manifest = Manifest {
+            workspace: Some(Workspace {
+                members: vec![PathBuf::new()],
+                default_members: vec![],
+            }),
+            ..Default::default()
+        };
+        manifest.features.insert(String::from("foo"), vec![]);
+        manifest
+            .features
+            .insert(String::from("default"), vec![String::from("foo")]);
+        manifest.dev_dependencies.insert(
+            String::from("b"),
+            Dependency {
+                path: PathBuf::from("../b"),
+            },
+        );
+        manifest.dependencies.insert(
+            String::from("a"),
+            Dependency {
+                path: PathBuf::from("../a"),
+            },
+        );
+        let mut test = Target::new("test", "test.rs");
+        test.harness = Some(false);
+        manifest.tests.push(test);
+        manifest.bins.push(Target::new("bin", "main.rs"));
+        manifest.package = Some(Package::new("pkg"));
+
+        assert_eq!(
+            manifest.to_string(),
+            r#"[package]
+name = "pkg"
+version = "0.1.0"
+authors = ["Example <author@example.com>"]
+
+[[bin]]
+name = "bin"
+path = "main.rs"
+
+[[test]]
+name = "test"
+path = "test.rs"
+harness = false
+
+[dependencies]
+a = { path = "../a" }
+
+[dev-dependencies]
+b = { path = "../b" }
+
+[features]
+default = ["foo"]
+foo = []
+
+[workspace]
+members = [
+    ".",
+]
+"#
+        );
+    }
+
+    fn sample_manifest() -> Manifest {
+        let mut manifest = Manifest {
+            package: Some(Package::new("pkg")),
+            lib: Some(Target::new("pkg", "lib.rs")),
+            bins: vec![Target::new("bin", "main.rs")],
+            ..Default::default()
+        };
+        manifest.dependencies.insert(
+            String::from("dep"),
+            Dependency {
+                path: PathBuf::from("../dep"),
+            },
+        );
+        manifest
+    }
+
+    #[test]
+    fn merge_into_empty() -> Result<(), toml_edit::TomlError> {
+        let merged = sample_manifest().merge_into("")?;
+        assert!(merged.starts_with(GENERATED_MARKER));
+        assert!(merged.ends_with(&sample_manifest().to_string()));
+        assert_eq!(sample_manifest().merge_into(&merged)?, merged);
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_into_hand_maintained() -> Result<(), toml_edit::TomlError> {
+        let existing = r#"# Maintained by hand
+[package]
+name = "old"  # must match the Buck rule
+version = "1.2.3"
+edition = "2018"
+
+[package.metadata.docs]
+all-features = true
+
+[[bin]]
+name = "tool"
+path = "tool.rs"
+
+[dependencies]
+serde = "1"
+dep = { path = "../old-dep", features = ["x"] }
+
+[profile.release]
+lto = true
+"#;
+        assert_eq!(
+            sample_manifest().merge_into(existing)?,
+            r#"# Maintained by hand
+[package]
+name = "pkg"  # must match the Buck rule
+version = "1.2.3"
+edition = "2018"
+
+[package.metadata.docs]
+all-features = true
+
+[[bin]]
+name = "tool"
+path = "tool.rs"
+
+[[bin]]
+name = "bin"
+path = "main.rs"
+
+[dependencies]
+serde = "1"
+dep = { path = "../dep", features = ["x"] }
+
+[profile.release]
+lto = true
+
+[lib]
+name = "pkg"
+path = "lib.rs"
+"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_into_generated() -> Result<(), toml_edit::TomlError> {
+        let mut old = sample_manifest();
+        old.package.as_mut().unwrap().license = Some(String::from("MIT"));
+        old.bins.push(Target::new("stale", "stale.rs"));
+        let existing = old.merge_into("")? + "\n[package.metadata.docs]\nall-features = true\n";
+
+        let merged = sample_manifest().merge_into(&existing)?;
+        assert_eq!(
+            merged,
+            sample_manifest().merge_into("")? + "\n[package.metadata.docs]\nall-features = true\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_inline_targets() -> Result<(), toml_edit::TomlError> {
+        let manifest = Manifest {
+            bins: vec![
+                Target::new("tool", "tool.rs"),
+                Target::new("bin", "main.rs"),
+            ],
+            ..Default::default()
+        };
+
+        let existing = "bin = [{ name = \"tool\", path = \"old.rs\", test = false }]\n";
+        assert_eq!(
+            manifest.merge_into(existing)?,
+            "bin = [{ name = \"tool\", path = \"tool.rs\", test = false }, \
+             { name = \"bin\", path = \"main.rs\" }]\n"
+        );
+
+        // Stale targets are removed from the generated manifests
+        let existing = format!(
+            "{}\n\nbin = [{{ name = \"stale\", path = \"stale.rs\" }}, \
+             {{ name = \"bin\", path = \"main.rs\" }}]\n",
+            GENERATED_MARKER
+        );
+        assert_eq!(
+            manifest.merge_into(&existing)?,
+            format!(
+                "{}\n\nbin = [{{ name = \"bin\", path = \"main.rs\" }}, \
+                 {{ name = \"tool\", path = \"tool.rs\" }}]\n",
+                GENERATED_MARKER
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_workspace_members() -> Result<(), toml_edit::TomlError> {
+        let manifest = Manifest {
+            workspace: Some(Workspace {
+                members: vec![PathBuf::from("bin"), PathBuf::from("lib")],
+                default_members: vec![],
+            }),
+            ..Default::default()
+        };
+
+        // Members added by hand are kept
+        let existing = "[workspace]\nmembers = [\"handwritten\", \"bin\"]\n";
+        assert_eq!(
+            manifest.merge_into(existing)?,
+            "[workspace]\nmembers = [\n    \"handwritten\",\n    \"bin\",\n    \"lib\",\n]\n"
+        );
+        let existing = "[workspace]\nmembers = [\"lib\", \"bin\"]  # sorted by hand\n";
+        assert_eq!(manifest.merge_into(existing)?, existing);
+
+        // Unlike in the generated manifests
+        let existing = format!("{}\n\n{}", GENERATED_MARKER, existing);
+        assert_eq!(
+            manifest.merge_into(&existing)?,
+            manifest
+                .merge_into("")?
+                .replace("\n]\n", "\n]  # sorted by hand\n")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_lints() -> Result<(), toml_edit::TomlError> {
+        let mut manifest = Manifest::default();
+        manifest
+            .lints
+            .entry(String::from("rust"))
+            .or_default()
+            .insert(
+                String::from("unsafe_code"),
+                Lint {
+                    level: LintLevel::Forbid,
+                    priority: 0,
+                },
+            );
+
+        let existing = "[lints.rust]\nunsafe_code = \"warn\"\nmissing_docs = \"warn\"\n";
+        assert_eq!(
+            manifest.merge_into(existing)?,
+            "[lints.rust]\nunsafe_code = \"forbid\"\nmissing_docs = \"warn\"\n"
+        );
+
+        let existing = "[package]\nname = \"pkg\"\n";
+        assert_eq!(
+            manifest.merge_into(existing)?,
+            "[package]\nname = \"pkg\"\n\n[lints.rust]\nunsafe_code = \"forbid\"\n"
+        );
+
+        // Packages inheriting the workspace lints can't set their own
+        let existing = "[lints]\nworkspace = true\n";
+        assert_eq!(manifest.merge_into(existing)?, existing);
+
+        Ok(())
+    }
+
+    #[test]
+    fn merge_profiles() -> Result<(), toml_edit::TomlError> {
+        let mut profile = Profile::default();
+        profile
+            .settings
+            .insert(String::from("lto"), ProfileValue::Bool(true));
+        profile.packages.insert(
+            String::from("pkg"),
+            vec![(String::from("opt-level"), ProfileValue::Integer(3))]
+                .into_iter()
+                .collect(),
+        );
+        let manifest = Manifest {
+            profiles: vec![(String::from("release"), profile)]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+
+        // Settings tuned by hand are kept, only the missing ones are added
+        let existing = "[profile.release]\nlto = false # slow\ndebug = true\n";
+        assert_eq!(
+            manifest.merge_into(existing)?,
+            "[profile.release]\nlto = false # slow\ndebug = true\n\n\
+             [profile.release.package.pkg]\nopt-level = 3\n"
+        );
+        assert_eq!(
+            manifest.conflicting_profile_settings(existing),
+            ["profile.release.lto"]
+        );
+
+        let existing = "[profile.release]\nlto = true # fast\n";
+        assert!(manifest.conflicting_profile_settings(existing).is_empty());
+
+        // Generated manifests get the settings which are no longer translated
+        // removed, e.g. when a flag is removed from the rules
+        let existing = format!(
+            "{}\n\n[profile.release]\nlto = false # slow\ncodegen-units = 1\n\n\
+             [profile.dev]\npanic = \"abort\"\n",
+            GENERATED_MARKER
+        );
+        assert_eq!(
+            manifest.merge_into(&existing)?,
+            format!(
+                "{}\n\n[profile.release]\nlto = true # slow\n\n\
+                 [profile.release.package.pkg]\nopt-level = 3\n",
+                GENERATED_MARKER
+            )
+        );
+        assert!(manifest.conflicting_profile_settings(&existing).is_empty());
+
+        Ok(())
+    }
+}
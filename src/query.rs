@@ -0,0 +1,325 @@
+//! Access to the build system, abstracted behind a query backend.
+//!
+//! The translation only needs a handful of Buck queries, which are expressed by
+//! the `QueryBackend` trait. Besides the Buck CLI itself (see `buck::BuckCli`),
+//! rules can be replayed from a recorded query output or provided directly,
+//! which allows to run the whole pipeline without Buck installed.
+
+use std::collections::{BTreeSet, VecDeque};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::bazel;
+use crate::buck::{self, BuildTarget, BuildTargetPattern, Rules};
+use crate::buck2;
+
+/// Build system whose query output is translated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frontend {
+    Buck,
+    Buck2,
+    Bazel,
+}
+
+impl Frontend {
+    /// Detects the build system of the project the directory belongs to,
+    /// defaulting to Buck.
+    pub fn detect(dir: &Path) -> Frontend {
+        if buck2::is_buck2_project(dir) {
+            Frontend::Buck2
+        } else if bazel::is_bazel_workspace(dir) {
+            Frontend::Bazel
+        } else {
+            Frontend::Buck
+        }
+    }
+}
+
+impl FromStr for Frontend {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "buck" => Ok(Frontend::Buck),
+            "buck2" => Ok(Frontend::Buck2),
+            "bazel" => Ok(Frontend::Bazel),
+            _ => Err(failure::format_err!("Unknown frontend {}", s)),
+        }
+    }
+}
+
+pub trait QueryBackend {
+    /// Returns the root of the Buck repository.
+    fn root(&self) -> Result<PathBuf, failure::Error>;
+    /// Returns the rules matching any of the patterns together with every rule
+    /// they transitively depend on (`deps(set(patterns))`).
+    fn query_deps(&self, patterns: &[BuildTargetPattern]) -> Result<Rules, failure::Error>;
+}
+
+/// Query backend answering queries from a fixed set of rules.
+#[derive(Debug)]
+pub struct InMemory {
+    root: PathBuf,
+    rules: Rules,
+}
+
+impl InMemory {
+    pub fn new(root: impl Into<PathBuf>, rules: Rules) -> Self {
+        InMemory {
+            root: root.into(),
+            rules,
+        }
+    }
+
+    /// Returns the targets which no other known rule depends on.
+    pub fn roots(&self) -> Vec<BuildTarget> {
+        let deps = self
+            .rules
+            .values()
+            .flat_map(|rule| &rule.common.deps)
+            .map(BuildTarget::unflavored)
+            .collect::<BTreeSet<_>>();
+
+        let mut roots = self
+            .rules
+            .keys()
+            .filter(|target| !deps.contains(target))
+            .cloned()
+            .collect::<Vec<_>>();
+        roots.sort();
+        roots
+    }
+
+    /// Returns the known target of the rule, ignoring flavors.
+    fn rule_target(&self, target: &BuildTarget) -> Option<&BuildTarget> {
+        self.rules
+            .get_key_value(&target.unflavored())
+            .map(|(target, _)| target)
+    }
+
+    /// Returns the known targets matching the pattern, which has to match at
+    /// least one.
+    fn matching(&self, pattern: &BuildTargetPattern) -> Result<Vec<&BuildTarget>, failure::Error> {
+        let targets = self
+            .rules
+            .keys()
+            .filter(|target| pattern.matches(target))
+            .collect::<Vec<_>>();
+        if targets.is_empty() {
+            Err(failure::format_err!("Unknown build target {}", pattern))
+        } else {
+            Ok(targets)
+        }
+    }
+
+    /// Returns the targets and every known target they transitively depend on.
+    /// Dependencies that aren't known are skipped.
+    fn closure<'a>(
+        &'a self,
+        targets: impl IntoIterator<Item = &'a BuildTarget>,
+    ) -> BTreeSet<&'a BuildTarget> {
+        let mut closure = BTreeSet::new();
+        let mut queue = targets.into_iter().collect::<VecDeque<_>>();
+        while let Some(target) = queue.pop_front() {
+            if !closure.insert(target) {
+                continue;
+            }
+            for dep in &self.rules[target].common.deps {
+                if let Some(dep) = self.rule_target(dep) {
+                    queue.push_back(dep);
+                }
+            }
+        }
+
+        closure
+    }
+
+    fn select<'a>(&self, targets: impl IntoIterator<Item = &'a BuildTarget>) -> Rules {
+        targets
+            .into_iter()
+            .map(|target| (target.clone(), self.rules[target].clone()))
+            .collect()
+    }
+}
+
+impl QueryBackend for InMemory {
+    fn root(&self) -> Result<PathBuf, failure::Error> {
+        Ok(self.root.clone())
+    }
+
+    fn query_deps(&self, patterns: &[BuildTargetPattern]) -> Result<Rules, failure::Error> {
+        let mut targets = vec![];
+        for pattern in patterns {
+            targets.extend(self.matching(pattern)?);
+        }
+        Ok(self.select(self.closure(targets)))
+    }
+}
+
+/// Query backend replaying a recorded Buck query output, such as the one saved
+/// with `--save-json`.
+#[derive(Debug)]
+pub struct Recorded(InMemory);
+
+impl Recorded {
+    pub fn from_json(
+        root: impl Into<PathBuf>,
+        frontend: Frontend,
+        json: &[u8],
+    ) -> Result<Self, failure::Error> {
+        let root = root.into();
+        let rules = match frontend {
+            Frontend::Buck => buck::from_bytes(json)?,
+            Frontend::Buck2 => buck2::from_bytes(json, &buck2::Cells::read(&root)?)?,
+            Frontend::Bazel => bazel::from_bytes(json)?,
+        };
+
+        Ok(Recorded(InMemory::new(root, rules)))
+    }
+
+    /// Reads the recorded output from a file or from stdin, if the path is `-`.
+    pub fn open(
+        root: impl Into<PathBuf>,
+        frontend: Frontend,
+        path: impl AsRef<Path>,
+    ) -> Result<Self, failure::Error> {
+        let path = path.as_ref();
+        let json = if path == Path::new("-") {
+            let mut json = vec![];
+            io::stdin().read_to_end(&mut json)?;
+            json
+        } else {
+            fs::read(path)?
+        };
+
+        Recorded::from_json(root, frontend, &json)
+    }
+
+    /// Returns the targets that were presumably queried to produce the
+    /// recording, i.e. the ones which no other recorded rule depends on.
+    pub fn roots(&self) -> Vec<BuildTarget> {
+        self.0.roots()
+    }
+}
+
+impl QueryBackend for Recorded {
+    fn root(&self) -> Result<PathBuf, failure::Error> {
+        self.0.root()
+    }
+
+    fn query_deps(&self, patterns: &[BuildTargetPattern]) -> Result<Rules, failure::Error> {
+        self.0.query_deps(patterns)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(s: &str) -> BuildTarget {
+        s.parse().unwrap()
+    }
+
+    fn pattern(s: &str) -> BuildTargetPattern {
+        s.parse().unwrap()
+    }
+
+    const INPUT: &str = r#"{
+        "//bin:bin" : {
+            "buck.base_path" : "bin",
+            "buck.direct_dependencies" : [],
+            "buck.type" : "rust_binary",
+            "deps" : [ "//lib:lib" ],
+            "name" : "bin",
+            "srcs" : [ "main.rs" ]
+        },
+        "//lib:lib" : {
+            "buck.base_path" : "lib",
+            "buck.direct_dependencies" : [],
+            "buck.type" : "rust_library",
+            "deps" : [ "//lib:util" ],
+            "name" : "lib",
+            "srcs" : [ "lib.rs" ]
+        },
+        "//lib:util" : {
+            "buck.base_path" : "lib",
+            "buck.direct_dependencies" : [],
+            "buck.type" : "rust_library",
+            "deps" : [],
+            "name" : "util",
+            "srcs" : [ "util.rs" ]
+        },
+        "//other:other" : {
+            "buck.base_path" : "other",
+            "buck.direct_dependencies" : [],
+            "buck.type" : "rust_library",
+            "deps" : [ "//lib:util" ],
+            "name" : "other",
+            "srcs" : [ "lib.rs" ]
+        }
+    }"#;
+
+    fn targets(rules: Rules) -> Vec<BuildTarget> {
+        let mut targets = rules.into_keys().collect::<Vec<_>>();
+        targets.sort();
+        targets
+    }
+
+    #[test]
+    fn recorded_queries() -> Result<(), failure::Error> {
+        let backend = Recorded::from_json("root", Frontend::Buck, INPUT.as_bytes())?;
+
+        assert_eq!(backend.root()?, PathBuf::from("root"));
+        assert_eq!(
+            backend.roots(),
+            vec![target("//bin:bin"), target("//other:other")]
+        );
+        assert_eq!(
+            targets(backend.query_deps(&[pattern("//lib:lib")])?),
+            vec![target("//lib:lib"), target("//lib:util")]
+        );
+        assert_eq!(
+            targets(backend.query_deps(&[pattern("//lib:"), pattern("//other/...")])?),
+            vec![
+                target("//lib:lib"),
+                target("//lib:util"),
+                target("//other:other")
+            ]
+        );
+        assert!(backend.query_deps(&[pattern("//missing:missing")]).is_err());
+        assert!(backend
+            .query_deps(&[pattern("//bin:bin"), pattern("//missing/...")])
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn replay_saved_json() -> Result<(), failure::Error> {
+        // As saved with --save-json and read back with --from-json
+        let path = std::env::temp_dir().join(format!("transantlator-{}.json", std::process::id()));
+        fs::write(&path, INPUT)?;
+        let backend = Recorded::open("root", Frontend::Buck, &path);
+        fs::remove_file(&path)?;
+
+        let backend = backend?;
+        assert_eq!(backend.root()?, PathBuf::from("root"));
+        assert_eq!(
+            backend.roots(),
+            vec![target("//bin:bin"), target("//other:other")]
+        );
+        assert_eq!(
+            targets(backend.query_deps(&[pattern("//bin:bin")])?),
+            vec![
+                target("//bin:bin"),
+                target("//lib:lib"),
+                target("//lib:util")
+            ]
+        );
+        assert!(Recorded::open("root", Frontend::Buck, path).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,358 @@
+//! Parsing of the `rustc_flags` rule attribute.
+//!
+//! Cargo doesn't accept arbitrary compiler flags, so the ones with a Cargo
+//! equivalent are recognized here to be translated. The rest is kept as is.
+
+use std::fmt;
+
+/// Lint level, ordered by strictness.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny,
+    Forbid,
+}
+
+impl LintLevel {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LintLevel::Allow => "allow",
+            LintLevel::Warn => "warn",
+            LintLevel::Deny => "deny",
+            LintLevel::Forbid => "forbid",
+        }
+    }
+
+    fn parse(level: &str) -> Option<LintLevel> {
+        match level {
+            "allow" => Some(LintLevel::Allow),
+            "warn" => Some(LintLevel::Warn),
+            "deny" => Some(LintLevel::Deny),
+            "forbid" => Some(LintLevel::Forbid),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LintLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// A lint, e.g. `dead_code` or `clippy::needless_return`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LintName {
+    /// `rust` for the lints of the compiler itself.
+    pub tool: String,
+    /// Name without the tool prefix, with dashes replaced by underscores.
+    pub name: String,
+}
+
+impl LintName {
+    pub fn parse(lint: &str) -> LintName {
+        let (tool, name) = lint.split_once("::").unwrap_or(("rust", lint));
+        LintName {
+            tool: tool.to_owned(),
+            name: name.replace('-', "_"),
+        }
+    }
+
+    /// Returns the level of the lint when no flags set it. Lints which aren't
+    /// known to be allowed or denied by default are assumed to warn.
+    pub fn default_level(&self) -> LintLevel {
+        const RUST_ALLOWED: &[&str] = &[
+            "absolute_paths_not_starting_with_crate",
+            "elided_lifetimes_in_paths",
+            "explicit_outlives_requirements",
+            "keyword_idents",
+            "let_underscore_drop",
+            "macro_use_extern_crate",
+            "meta_variable_misuse",
+            "missing_copy_implementations",
+            "missing_debug_implementations",
+            "missing_docs",
+            "rust_2018_idioms",
+            "single_use_lifetimes",
+            "trivial_casts",
+            "trivial_numeric_casts",
+            "unreachable_pub",
+            "unsafe_code",
+            "unsafe_op_in_unsafe_fn",
+            "unused_crate_dependencies",
+            "unused_extern_crates",
+            "unused_import_braces",
+            "unused_lifetimes",
+            "unused_qualifications",
+            "unused_results",
+            "variant_size_differences",
+        ];
+        const RUST_DENIED: &[&str] = &[
+            "ambiguous_associated_items",
+            "arithmetic_overflow",
+            "conflicting_repr_hints",
+            "enum_intrinsics_non_enums",
+            "invalid_type_param_default",
+            "mutable_transmutes",
+            "no_mangle_const_items",
+            "overflowing_literals",
+            "unconditional_panic",
+            "unknown_crate_types",
+        ];
+        const CLIPPY_ALLOWED: &[&str] = &["cargo", "nursery", "pedantic", "restriction"];
+
+        let name = self.name.as_str();
+        match self.tool.as_str() {
+            "rust" if RUST_ALLOWED.contains(&name) => LintLevel::Allow,
+            "rust" if RUST_DENIED.contains(&name) => LintLevel::Deny,
+            "clippy" if CLIPPY_ALLOWED.contains(&name) => LintLevel::Allow,
+            "clippy" if name == "correctness" => LintLevel::Deny,
+            _ => LintLevel::Warn,
+        }
+    }
+
+    /// Whether the lint is a group of other lints, such as `unused` or
+    /// `clippy::pedantic`.
+    pub fn is_group(&self) -> bool {
+        const RUST_GROUPS: &[&str] = &[
+            "warnings",
+            "future_incompatible",
+            "keyword_idents",
+            "let_underscore",
+            "nonstandard_style",
+            "refining_impl_trait",
+            "rust_2018_compatibility",
+            "rust_2018_idioms",
+            "rust_2021_compatibility",
+            "rust_2024_compatibility",
+            "unknown_or_malformed_diagnostic_attributes",
+            "unused",
+        ];
+        const CLIPPY_GROUPS: &[&str] = &[
+            "all",
+            "cargo",
+            "complexity",
+            "correctness",
+            "nursery",
+            "pedantic",
+            "perf",
+            "restriction",
+            "style",
+            "suspicious",
+        ];
+
+        let groups = match self.tool.as_str() {
+            "rust" => RUST_GROUPS,
+            "clippy" => CLIPPY_GROUPS,
+            _ => &[],
+        };
+        groups.contains(&self.name.as_str())
+    }
+}
+
+impl fmt::Display for LintName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.tool.as_str() {
+            "rust" => write!(f, "{}", self.name),
+            tool => write!(f, "{}::{}", tool, self.name),
+        }
+    }
+}
+
+/// A configuration option set with `--cfg`, e.g. `foo` or `key="value"`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Cfg {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl Cfg {
+    pub fn parse(cfg: &str) -> Cfg {
+        match cfg.split_once('=') {
+            Some((name, value)) => {
+                let value = value.trim();
+                let unquoted = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'));
+                Cfg {
+                    name: name.trim().to_owned(),
+                    value: Some(unquoted.unwrap_or(value).to_owned()),
+                }
+            }
+            None => Cfg {
+                name: cfg.trim().to_owned(),
+                value: None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Cfg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}={:?}", self.name, value),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RustcFlags {
+    /// Lint levels, in the order they are set (so the later ones override the
+    /// earlier ones).
+    pub lints: Vec<(LintName, LintLevel)>,
+    pub cap_lints: Option<LintLevel>,
+    pub cfgs: Vec<Cfg>,
+    /// Codegen options set with `-C`, in the order they are set. `-O` and `-g`
+    /// are included as `opt-level=2` and `debuginfo=2`.
+    pub codegen: Vec<(String, Option<String>)>,
+    /// Flags which aren't recognized.
+    pub other: Vec<String>,
+}
+
+impl RustcFlags {
+    pub fn parse(flags: &[String]) -> RustcFlags {
+        let mut parsed = RustcFlags::default();
+
+        let mut flags = flags.iter();
+        while let Some(flag) = flags.next() {
+            // Values are either joined with the flag or passed as the next one
+            let mut value = |short: &str, long: &str| {
+                let long_value = flag
+                    .strip_prefix(long)
+                    .and_then(|value| value.strip_prefix('='));
+                let short_value = Some(short)
+                    .filter(|short| !short.is_empty())
+                    .and_then(|short| flag.strip_prefix(short))
+                    .filter(|value| !value.is_empty());
+                if flag == short || flag == long {
+                    flags.next().cloned()
+                } else {
+                    long_value.or(short_value).map(str::to_owned)
+                }
+            };
+
+            let lint = [
+                ("-A", "--allow", LintLevel::Allow),
+                ("-W", "--warn", LintLevel::Warn),
+                ("-D", "--deny", LintLevel::Deny),
+                ("-F", "--forbid", LintLevel::Forbid),
+            ]
+            .iter()
+            .find_map(|&(short, long, level)| Some((value(short, long)?, level)));
+
+            if let Some((lint, level)) = lint {
+                parsed.lints.push((LintName::parse(&lint), level));
+            } else if let Some(option) = value("-C", "--codegen") {
+                let (key, value) = match option.split_once('=') {
+                    Some((key, value)) => (key, Some(value.to_owned())),
+                    None => (option.as_str(), None),
+                };
+                parsed.codegen.push((key.to_owned(), value));
+            } else if flag == "-O" {
+                parsed.codegen.push(("opt-level".into(), Some("2".into())));
+            } else if flag == "-g" {
+                parsed.codegen.push(("debuginfo".into(), Some("2".into())));
+            } else if let Some(cfg) = value("", "--cfg") {
+                parsed.cfgs.push(Cfg::parse(&cfg));
+            } else if let Some(cap) = value("", "--cap-lints") {
+                // Invalid levels are rejected by rustc anyway
+                parsed.cap_lints = LintLevel::parse(&cap).or(parsed.cap_lints);
+            } else {
+                parsed.other.push(flag.clone());
+            }
+        }
+
+        parsed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_lint_flags() {
+        let flags = [
+            "-D",
+            "warnings",
+            "-Adead-code",
+            "--warn=clippy::pedantic",
+            "--forbid",
+            "unsafe_code",
+            "--cap-lints",
+            "warn",
+            "-Ctarget-cpu=native",
+        ];
+        let flags = RustcFlags::parse(&flags.map(String::from));
+
+        let lints = flags
+            .lints
+            .iter()
+            .map(|(lint, level)| (lint.to_string(), *level))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lints,
+            [
+                (String::from("warnings"), LintLevel::Deny),
+                (String::from("dead_code"), LintLevel::Allow),
+                (String::from("clippy::pedantic"), LintLevel::Warn),
+                (String::from("unsafe_code"), LintLevel::Forbid),
+            ]
+        );
+        assert!(flags.lints[2].0.is_group());
+        assert!(!flags.lints[3].0.is_group());
+        assert_eq!(flags.lints[2].0.default_level(), LintLevel::Allow);
+        assert_eq!(flags.lints[3].0.default_level(), LintLevel::Allow);
+        assert_eq!(flags.lints[1].0.default_level(), LintLevel::Warn);
+        assert_eq!(flags.cap_lints, Some(LintLevel::Warn));
+        assert!(flags.other.is_empty());
+    }
+
+    #[test]
+    fn parse_codegen_flags() {
+        let flags = [
+            "-C",
+            "lto",
+            "-Copt-level=3",
+            "--codegen=panic=abort",
+            "-g",
+            "--edition=2021",
+        ];
+        let flags = RustcFlags::parse(&flags.map(String::from));
+
+        let option = |key: &str, value: Option<&str>| (key.to_owned(), value.map(str::to_owned));
+        assert_eq!(
+            flags.codegen,
+            [
+                option("lto", None),
+                option("opt-level", Some("3")),
+                option("panic", Some("abort")),
+                option("debuginfo", Some("2")),
+            ]
+        );
+        assert_eq!(flags.other, ["--edition=2021"]);
+    }
+
+    #[test]
+    fn parse_cfg_flags() {
+        let flags = [
+            "--cfg",
+            "foo",
+            "--cfg=key=\"value\"",
+            "--cfg",
+            "bar = \"a b\"",
+        ];
+        let flags = RustcFlags::parse(&flags.map(String::from));
+
+        let cfgs = flags
+            .cfgs
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(cfgs, ["foo", "key=\"value\"", "bar=\"a b\""]);
+        assert_eq!(flags.cfgs[1].value.as_deref(), Some("value"));
+        assert!(flags.other.is_empty());
+    }
+}
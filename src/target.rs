@@ -0,0 +1,377 @@
+//! Build targets, identifying build rules.
+//!
+//! A fully qualified build target has the form `cell//path/to:name#flavors`.
+//! The cell is optional and omitted for targets of the root cell, whereas
+//! flavors select a variant of the rule (e.g. `#check`) and are also optional.
+//! The `//path/to` shorthand refers to the rule named after the last path
+//! component, i.e. `//path/to:to`.
+//!
+//! Build target patterns match sets of targets and can be either:
+//! - `PUBLIC`, matching every target,
+//! - `//path/to:name`, matching the target itself (regardless of flavors),
+//! - `//path/to:`, matching every target of the buildfile,
+//! - `//path/to/...`, matching every target of the buildfile and of the ones in
+//!   its subdirectories.
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BuildTarget {
+    /// `None` for the root cell.
+    cell: Option<String>,
+    /// Path of the buildfile directory, relative to the cell root.
+    base_path: String,
+    name: String,
+    /// Sorted and deduplicated, since the order of flavors is irrelevant.
+    flavors: Vec<String>,
+}
+
+impl BuildTarget {
+    pub fn cell(&self) -> Option<&str> {
+        self.cell.as_deref()
+    }
+
+    pub fn base_path(&self) -> &Path {
+        Path::new(&self.base_path)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the target of the rule itself, without any flavors.
+    pub fn unflavored(&self) -> BuildTarget {
+        BuildTarget {
+            flavors: vec![],
+            ..self.clone()
+        }
+    }
+}
+
+impl fmt::Display for BuildTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(cell) = &self.cell {
+            write!(f, "{}", cell)?;
+        }
+        write!(f, "//{}:{}", self.base_path, self.name)?;
+        if !self.flavors.is_empty() {
+            write!(f, "#{}", self.flavors.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseTargetError {
+    target: String,
+    reason: &'static str,
+}
+
+impl fmt::Display for ParseTargetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid build target `{}`: {}", self.target, self.reason)
+    }
+}
+
+impl std::error::Error for ParseTargetError {}
+
+impl FromStr for BuildTarget {
+    type Err = ParseTargetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = |reason| ParseTargetError {
+            target: s.to_owned(),
+            reason,
+        };
+
+        let (cell, rest) = s
+            .split_once("//")
+            .ok_or_else(|| err("expected `//` or `cell//` prefix"))?;
+        // Canonical names of Bazel repositories contain `~` (or `+` since
+        // Bazel 8), e.g. `rules_rust~~crate~crates__serde-1.0.197`
+        let valid_cell = |c: char| c.is_ascii_alphanumeric() || "_-.~+".contains(c);
+        if !cell.chars().all(valid_cell) {
+            return Err(err("invalid cell name"));
+        }
+
+        let (rest, flavors) = match rest.split_once('#') {
+            Some((rest, flavors)) => (rest, flavors.split(',').collect::<Vec<_>>()),
+            None => (rest, vec![]),
+        };
+        if flavors.iter().any(|flavor| flavor.is_empty()) {
+            return Err(err("empty flavor"));
+        }
+
+        let (base_path, name) = match rest.split_once(':') {
+            Some((base_path, name)) => (base_path, name),
+            None => (rest, rest.rsplit('/').next().unwrap_or_default()),
+        };
+        if base_path
+            .split('/')
+            .any(|component| component == "." || component == "..")
+            || base_path.starts_with('/')
+            || base_path.ends_with('/')
+            || base_path.contains("//")
+        {
+            return Err(err("invalid package path"));
+        }
+        if name.is_empty() {
+            return Err(err("missing rule name"));
+        }
+        if name.contains(|c: char| c == ':' || c.is_whitespace()) {
+            return Err(err("invalid rule name"));
+        }
+
+        let mut flavors = flavors.into_iter().map(str::to_owned).collect::<Vec<_>>();
+        flavors.sort();
+        flavors.dedup();
+
+        Ok(BuildTarget {
+            cell: Some(cell.to_owned()).filter(|cell| !cell.is_empty()),
+            base_path: base_path.to_owned(),
+            name: name.to_owned(),
+            flavors,
+        })
+    }
+}
+
+impl Serialize for BuildTarget {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for BuildTarget {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let target = String::deserialize(deserializer)?;
+        target.parse().map_err(de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BuildTargetPattern {
+    Public,
+    Target(BuildTarget),
+    Package {
+        cell: Option<String>,
+        base_path: String,
+    },
+    Recursive {
+        cell: Option<String>,
+        base_path: String,
+    },
+}
+
+impl BuildTargetPattern {
+    pub fn matches(&self, target: &BuildTarget) -> bool {
+        match self {
+            BuildTargetPattern::Public => true,
+            BuildTargetPattern::Target(pattern) => pattern.unflavored() == target.unflavored(),
+            BuildTargetPattern::Package { cell, base_path } => {
+                *cell == target.cell && *base_path == target.base_path
+            }
+            BuildTargetPattern::Recursive { cell, base_path } => {
+                *cell == target.cell && target.base_path().starts_with(base_path)
+            }
+        }
+    }
+}
+
+impl fmt::Display for BuildTargetPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cell = |cell: &Option<String>| cell.clone().unwrap_or_default();
+        match self {
+            BuildTargetPattern::Public => write!(f, "PUBLIC"),
+            BuildTargetPattern::Target(target) => write!(f, "{}", target),
+            BuildTargetPattern::Package { cell: c, base_path } => {
+                write!(f, "{}//{}:", cell(c), base_path)
+            }
+            BuildTargetPattern::Recursive { cell: c, base_path } if base_path.is_empty() => {
+                write!(f, "{}//...", cell(c))
+            }
+            BuildTargetPattern::Recursive { cell: c, base_path } => {
+                write!(f, "{}//{}/...", cell(c), base_path)
+            }
+        }
+    }
+}
+
+impl FromStr for BuildTargetPattern {
+    type Err = ParseTargetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "PUBLIC" {
+            return Ok(BuildTargetPattern::Public);
+        }
+
+        // Reuse target validation by parsing the pattern with a dummy name
+        let package = |pattern: &str| {
+            let BuildTarget {
+                cell, base_path, ..
+            } = format!("{}:_", pattern)
+                .parse::<BuildTarget>()
+                .map_err(|err| ParseTargetError {
+                    target: s.to_owned(),
+                    ..err
+                })?;
+            Ok((cell, base_path))
+        };
+
+        if let Some(pattern) = s.strip_suffix("...") {
+            // `cell//...` matches the whole cell, otherwise `/...` follows a path
+            let pattern = match pattern.strip_suffix('/') {
+                Some(pattern) if !pattern.ends_with('/') => pattern,
+                _ if pattern.ends_with("//") => pattern,
+                _ => {
+                    return Err(ParseTargetError {
+                        target: s.to_owned(),
+                        reason: "invalid package path",
+                    })
+                }
+            };
+            let (cell, base_path) = package(pattern)?;
+            Ok(BuildTargetPattern::Recursive { cell, base_path })
+        } else if let Some(pattern) = s.strip_suffix(':') {
+            let (cell, base_path) = package(pattern)?;
+            Ok(BuildTargetPattern::Package { cell, base_path })
+        } else {
+            s.parse().map(BuildTargetPattern::Target)
+        }
+    }
+}
+
+impl Serialize for BuildTargetPattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for BuildTargetPattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        pattern.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(s: &str) -> BuildTarget {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_targets() {
+        let foo = target("//path/to:foo");
+        assert_eq!(foo.cell(), None);
+        assert_eq!(foo.base_path(), Path::new("path/to"));
+        assert_eq!(foo.name(), "foo");
+
+        let bar = target("cell//:bar#check,default");
+        assert_eq!(bar.cell(), Some("cell"));
+        assert_eq!(bar.base_path(), Path::new(""));
+        assert_eq!(bar.name(), "bar");
+        assert_eq!(bar.to_string(), "cell//:bar#check,default");
+        assert_eq!(bar.unflavored(), target("cell//:bar"));
+
+        let serde = target("rules_rust~~crate~crates__serde-1.0.197//:serde");
+        assert_eq!(
+            serde.cell(),
+            Some("rules_rust~~crate~crates__serde-1.0.197")
+        );
+        assert_eq!(serde.name(), "serde");
+        assert!("rules_rust++crate+crates__serde-1.0.197//:serde"
+            .parse::<BuildTarget>()
+            .is_ok());
+    }
+
+    #[test]
+    fn normalize_targets() {
+        assert_eq!(target("//path/to").to_string(), "//path/to:to");
+        assert_eq!(target("//foo:bar#b,a,b").to_string(), "//foo:bar#a,b");
+        assert_eq!(target("cell//foo:bar").to_string(), "cell//foo:bar");
+        assert_eq!(target("//foo"), target("//foo:foo"));
+    }
+
+    #[test]
+    fn reject_invalid_targets() {
+        for invalid in &[
+            "foo:bar",
+            ":bar",
+            "//",
+            "//foo:",
+            "//foo/:bar",
+            "//foo//bar:baz",
+            "//../foo:bar",
+            "//foo:bar:baz",
+            "//foo:bar#",
+            "ce ll//foo:bar",
+        ] {
+            assert!(invalid.parse::<BuildTarget>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn order_targets() {
+        let mut targets = [
+            target("cell//a:a"),
+            target("//b:a"),
+            target("//a:b#check"),
+            target("//a:b"),
+            target("//a/b:a"),
+        ];
+        targets.sort();
+        let targets = targets.iter().map(ToString::to_string).collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            ["//a:b", "//a:b#check", "//a/b:a", "//b:a", "cell//a:a"]
+        );
+    }
+
+    #[test]
+    fn match_patterns() {
+        let pattern = |s: &str| s.parse::<BuildTargetPattern>().unwrap();
+        let foo = target("//foo:foo");
+        let foo_bar = target("//foo/bar:baz#check");
+        let other = target("cell//foo:foo");
+
+        assert!(pattern("PUBLIC").matches(&foo));
+        assert!(pattern("//foo:foo").matches(&foo));
+        assert!(pattern("//foo/bar:baz").matches(&foo_bar));
+        assert!(!pattern("//foo:foo").matches(&other));
+
+        assert!(pattern("//foo:").matches(&foo));
+        assert!(!pattern("//foo:").matches(&foo_bar));
+
+        assert!(pattern("//foo/...").matches(&foo));
+        assert!(pattern("//foo/...").matches(&foo_bar));
+        assert!(pattern("//...").matches(&foo_bar));
+        assert!(!pattern("//fo/...").matches(&foo));
+        assert!(!pattern("//...").matches(&other));
+        assert!(pattern("cell//...").matches(&other));
+
+        for p in &[
+            "PUBLIC",
+            "//foo:bar",
+            "//foo:",
+            "//foo/...",
+            "//...",
+            "cell//:",
+        ] {
+            assert_eq!(pattern(p).to_string(), *p);
+        }
+        for invalid in &["foo/...", "//foo...", "//foo/../...", "//foo/:", "PRIVATE"] {
+            assert!(
+                invalid.parse::<BuildTargetPattern>().is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+}
@@ -3,306 +3,2964 @@
 //! There are key differences between Buck and Cargo when it comes to packaging.
 //! One of them is that there can be only one [lib] per each Cargo.toml, whereas
 //! Buck can define multiple `rust_library` rules for a given BUCK buildfile.
-//! These should be further translated to Cargo workspaces to work around that.
+//! These are translated to synthetic packages, one for every additional
+//! library, and every generated package is tied together by a single Cargo
+//! workspace at the Buck root.
 //! Another difference is that integration tests can be only specified as a part
 //! of a given package, whereas Buck allows to specify `tests` as a set of build
-//! targets, potentially outside the given package.
+//! targets, potentially outside the given package. These are translated as
+//! [[test]] targets of the package of the rule that lists them in `tests`.
 //! In addition to that, unit test targets are implicit in Cargo but these are
 //! explicitly generated as separate *-unittest rules in Buck.
+//! Lastly, grouping rules by buildfile can make packages depend on each other,
+//! which Cargo forbids even though there is no cycle between the rules. Such
+//! cycles are reported or, if requested, broken by moving the offending
+//! binaries into synthetic packages of their own.
 
-// TODO:
-// * Support licenses
-// * Support features
-// * Support test targets
-// * Generate Cargo workspaces for multiple libraries in the same buildfile
-// * Coalesce dependencies for each Buck build target
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+
+use similar::TextDiff;
+
+use petgraph::algo::tarjan_scc;
+use petgraph::graphmap::DiGraphMap;
+
+use crate::buck::{BuildRule, BuildTarget, BuildTargetPattern, Rules};
+use crate::build_script::{self, BuildScript};
+use crate::diagnostics::{Diagnostic, Diagnostics};
+use crate::graph;
+use crate::license;
+use crate::manifest::{self, Dependency, Manifest, Profile, ProfileValue, Target, Workspace};
+use crate::query::QueryBackend;
+use crate::rustc_flags::{Cfg, LintLevel, LintName, RustcFlags};
+
+/// Directory (relative to the buildfile) containing synthetic packages, which
+/// are generated for every additional library defined in a single buildfile.
+const SYNTHETIC_PKG_DIR: &str = "cargo-libs";
+/// Directory (relative to the buildfile) containing synthetic packages of the
+/// binaries split off to break dependency cycles between packages.
+const SYNTHETIC_BIN_PKG_DIR: &str = "cargo-bins";
+
+/// Profile settings which change the behavior of the code (rather than just
+/// its performance or debuggability), so they are set in the `dev` profile as
+/// well as in the `release` one.
+const DEV_PROFILE_SETTINGS: &[&str] = &["debug-assertions", "overflow-checks", "panic", "rpath"];
+/// Profile settings which Cargo only accepts for the whole workspace, rather
+/// than for single packages.
+const WORKSPACE_PROFILE_SETTINGS: &[&str] = &["lto", "panic", "rpath"];
+
+/// State shared across translation of every package.
+pub struct Context<'a> {
+    buck_root: &'a Path,
+    /// Path to the Buck root, relative to the root of the generated Cargo
+    /// tree. Empty if the packages are generated in the Buck tree itself.
+    source_root: PathBuf,
+    rules: &'a Rules,
+    /// Directory of the package (relative to the Buck root) that each
+    /// supported rule is translated into.
+    package_dirs: HashMap<&'a BuildTarget, PathBuf>,
+    /// Binaries moved into packages of their own to break dependency cycles.
+    split_bins: HashSet<&'a BuildTarget>,
+}
+
+impl<'a> Context<'a> {
+    pub fn new(buck_root: &'a Path, rules: &'a Rules) -> Context<'a> {
+        let cx = Context {
+            buck_root,
+            source_root: PathBuf::new(),
+            rules,
+            package_dirs: assign_packages(rules, &HashSet::new()),
+            split_bins: HashSet::new(),
+        };
+
+        // A binary depending on an additional library of its buildfile makes
+        // the buildfile package and the synthetic one depend on each other
+        // whenever the library depends on the first one. Such cycles are only
+        // due to the package layout, so they are always broken.
+        cx.split_cycle_bins(|cycle| {
+            let base = &cycle.packages[0];
+            let synthetic = base.join(SYNTHETIC_PKG_DIR);
+            cycle.packages[1..]
+                .iter()
+                .all(|pkg| pkg.starts_with(&synthetic))
+        })
+    }
+
+    /// Moves the binaries which make packages depend on each other into
+    /// packages of their own. Nothing depends on binaries, so this breaks every
+    /// cycle (libraries alone can't form one, since Buck forbids cycles).
+    pub fn split_package_cycles(self) -> Context<'a> {
+        self.split_cycle_bins(|_| true)
+    }
+
+    /// Moves the binaries making up the selected package cycles into packages
+    /// of their own.
+    fn split_cycle_bins(self, select: impl Fn(&PackageCycle) -> bool) -> Context<'a> {
+        let rules = self.rules;
+        let mut split_bins = package_cycles(&self)
+            .iter()
+            .filter(|cycle| select(cycle))
+            .flat_map(|cycle| &cycle.deps)
+            .map(|&(target, _)| target)
+            .filter(|target| rules[*target].typ.is_binary())
+            .collect::<HashSet<_>>();
+        if split_bins.is_empty() {
+            return self;
+        }
+
+        split_bins.extend(&self.split_bins);
+        Context {
+            package_dirs: assign_packages(rules, &split_bins),
+            split_bins,
+            ..self
+        }
+    }
+
+    /// Generates the packages in the existing `out_dir` directory, mirroring
+    /// the Buck tree layout, rather than in the Buck tree itself.
+    pub fn with_out_dir(mut self, out_dir: &Path) -> io::Result<Context<'a>> {
+        let out_dir = out_dir.canonicalize()?;
+        let buck_root = self.buck_root.canonicalize()?;
+
+        self.source_root = if out_dir == buck_root {
+            PathBuf::new()
+        } else {
+            relative_path(&out_dir, &buck_root)
+        };
+        Ok(self)
+    }
+
+    /// Returns the directory of the package that the rule is translated into
+    /// or `None` if the rule is not supported.
+    fn package_dir(&self, target: &BuildTarget) -> Option<&Path> {
+        self.package_dirs.get(target).map(PathBuf::as_path)
+    }
+
+    /// Returns path to the source file (relative to the `base_path` buildfile
+    /// directory) relative to the generated package directory.
+    fn source_path(&self, pkg_dir: &Path, base_path: &Path, file: impl AsRef<Path>) -> PathBuf {
+        let source_dir = self.source_root.join(base_path);
+        if pkg_dir == source_dir {
+            file.as_ref().to_owned()
+        } else {
+            relative_path(pkg_dir, &source_dir).join(file)
+        }
+    }
+
+    /// Returns path to the crate root of the rule, relative to the generated
+    /// package directory.
+    fn crate_root_path(
+        &self,
+        pkg_dir: &Path,
+        target: &BuildTarget,
+        rule: &BuildRule,
+    ) -> Result<PathBuf, Box<Diagnostic>> {
+        let crate_root = rule.typ.crate_root().ok_or_else(|| {
+            let message = format!("Couldn't determine the crate root of {}", target);
+            let error = Diagnostic::error("no-crate-root", message)
+                .with_target(target, &rule.base_path)
+                .with_suggestion("Set `crate_root` of the rule");
+            Box::new(error)
+        })?;
+        Ok(self.source_path(pkg_dir, &rule.base_path, crate_root))
+    }
+}
+
+/// Assigns every supported rule the directory of the package (relative to the
+/// Buck root) it's translated into. Binaries are translated into the buildfile
+/// package, unless they are in `split_bins`.
+fn assign_packages<'a>(
+    rules: &'a Rules,
+    split_bins: &HashSet<&BuildTarget>,
+) -> HashMap<&'a BuildTarget, PathBuf> {
+    let mut libs_by_dir = HashMap::<_, Vec<_>>::new();
+    for (target, rule) in rules {
+        if rule.typ.is_library() && rule.typ.is_supported() {
+            libs_by_dir
+                .entry(&rule.base_path)
+                .or_default()
+                .push((target, rule));
+        }
+    }
+
+    let mut package_dirs = HashMap::new();
+    for (base_path, mut libs) in libs_by_dir {
+        // Only the first library (in a deterministic order) can stay in the
+        // buildfile package, others need a package of their own.
+        libs.sort_by_key(|(target, _)| *target);
+        for (idx, (target, rule)) in libs.into_iter().enumerate() {
+            let dir = if idx == 0 {
+                base_path.clone()
+            } else {
+                base_path
+                    .join(SYNTHETIC_PKG_DIR)
+                    .join(rule.typ.krate().unwrap())
+            };
+            package_dirs.insert(target, dir);
+        }
+    }
+
+    let mut sorted = rules
+        .iter()
+        .filter(|(_, r)| r.typ.is_supported())
+        .collect::<Vec<_>>();
+    sorted.sort_by_key(|(target, _)| *target);
+
+    for &(target, rule) in &sorted {
+        if rule.typ.is_binary() && !rule.typ.is_test() {
+            let dir = if split_bins.contains(target) {
+                rule.base_path
+                    .join(SYNTHETIC_BIN_PKG_DIR)
+                    .join(rule.typ.krate().unwrap())
+            } else {
+                rule.base_path.clone()
+            };
+            package_dirs.insert(target, dir);
+        }
+    }
+
+    // Tests are attached to the package of the rule which lists them in
+    // `tests`, preferring libraries over binaries.
+    let mut tested_by = HashMap::new();
+    let testers = sorted.iter().filter(|(_, r)| r.typ.is_library()).chain(
+        sorted
+            .iter()
+            .filter(|(_, r)| r.typ.is_binary() && !r.typ.is_test()),
+    );
+    for (target, rule) in testers {
+        for test in rule.typ.tests() {
+            tested_by.entry(test.unflavored()).or_insert(*target);
+        }
+    }
+    for &(target, rule) in &sorted {
+        if rule.typ.is_test() {
+            let dir = match tested_by.get(target) {
+                Some(tester) => package_dirs[tester].clone(),
+                None => rule.base_path.clone(),
+            };
+            package_dirs.insert(target, dir);
+        }
+    }
+
+    package_dirs
+}
+
+/// A single Cargo package generated from (a subset of) buildfile rules.
+struct Package<'a> {
+    /// Directory of the package, relative to the Buck root.
+    dir: &'a Path,
+    rules: Vec<(&'a BuildTarget, &'a BuildRule)>,
+}
+
+/// Cargo.toml manifest or build script updated by the translation.
+pub struct OutputFile {
+    /// Path to the file, relative to the root of the generated Cargo tree.
+    pub path: PathBuf,
+    /// Contents of the file before the translation, if it existed.
+    pub existing: Option<String>,
+    pub contents: String,
+}
+
+impl OutputFile {
+    pub fn is_changed(&self) -> bool {
+        self.existing.as_ref() != Some(&self.contents)
+    }
+
+    /// Returns a unified diff between the existing and the new contents.
+    pub fn diff(&self) -> String {
+        let old_path = match self.existing {
+            Some(..) => format!("a/{}", self.path.display()),
+            None => String::from("/dev/null"),
+        };
+        let new_path = format!("b/{}", self.path.display());
+
+        let existing = self.existing.as_deref().unwrap_or_default();
+        TextDiff::from_lines(existing, &self.contents)
+            .unified_diff()
+            .header(&old_path, &new_path)
+            .to_string()
+    }
+
+    pub fn write(&self, out_root: &Path) -> io::Result<()> {
+        let path = out_root.join(&self.path);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+
+        file.write_all(self.contents.as_bytes())
+    }
+}
+
+/// Result of translating the requested targets.
+pub struct Translation {
+    pub buck_root: PathBuf,
+    /// Every rule queried for the requested targets.
+    pub rules: Rules,
+    /// Empty if there are any errors among the diagnostics.
+    pub files: Vec<OutputFile>,
+    pub diagnostics: Diagnostics,
+}
+
+/// Selects which of the queried rules are translated.
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// If not empty, only the rules matching any of these are translated.
+    pub include: Vec<BuildTargetPattern>,
+    pub exclude: Vec<BuildTargetPattern>,
+}
+
+impl Filter {
+    pub fn allows(&self, target: &BuildTarget) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(target)))
+            && !self.exclude.iter().any(|p| p.matches(target))
+    }
+}
+
+/// Queries the backend for the targets matching the requested patterns and
+/// their dependencies (all in one query, so that shared dependencies are
+/// translated only once) and translates them into Cargo.toml manifests, merged
+/// with the ones that already exist in the output directory (the Buck root, if
+/// not specified).
+pub fn translate_rules(
+    backend: &dyn QueryBackend,
+    out_dir: Option<&Path>,
+    requested: &[BuildTargetPattern],
+    filter: &Filter,
+    split_cycles: bool,
+) -> Result<Translation, failure::Error> {
+    let buck_root = backend.root()?;
+    let mut rules = backend.query_deps(requested)?;
+
+    // Rules which are filtered out are treated as if they were never there
+    rules.retain(|target, _| filter.allows(target));
+    for rule in rules.values_mut() {
+        rule.common.deps.retain(|dep| filter.allows(dep));
+        rule.direct_dependencies.retain(|dep| filter.allows(dep));
+    }
+
+    let mut diagnostics = Diagnostics::default();
+    let files = translate_manifests(
+        &buck_root,
+        out_dir,
+        &rules,
+        requested,
+        split_cycles,
+        &mut diagnostics,
+    )?;
+
+    Ok(Translation {
+        buck_root,
+        rules,
+        files,
+        diagnostics,
+    })
+}
+
+/// Translates the rules into Cargo.toml manifests, including the workspace one
+/// at the Buck root, and merges them with the existing ones. Packages of the
+/// `requested` targets are set as the default workspace members.
+///
+/// The manifests are generated in the Buck tree, unless `out_dir` is given.
+/// No manifests are generated if any of the rules can't be translated, in which
+/// case the errors are added to `diagnostics`. Dependency cycles between the
+/// packages are such errors, unless `split_cycles` is set to break them.
+fn translate_manifests(
+    buck_root: &Path,
+    out_dir: Option<&Path>,
+    rules: &Rules,
+    requested: &[BuildTargetPattern],
+    split_cycles: bool,
+    diagnostics: &mut Diagnostics,
+) -> Result<Vec<OutputFile>, failure::Error> {
+    let mut cx = match out_dir {
+        Some(out_dir) => Context::new(buck_root, rules).with_out_dir(out_dir)?,
+        None => Context::new(buck_root, rules),
+    };
+    if split_cycles {
+        cx = cx.split_package_cycles();
+    }
+    let out_root = out_dir.unwrap_or(buck_root);
+
+    diagnostics.extend(check_custom_harnesses(buck_root, rules));
+    diagnostics.extend(check_visibility(&cx));
+    diagnostics.extend(check_lints(&cx));
+    diagnostics.extend(check_rustc_flags(&cx));
+    let cycles = check_package_cycles(&cx);
+    if !cycles.is_empty() {
+        diagnostics.extend(cycles);
+        return Ok(vec![]);
+    }
+    let mut manifests = match translate_packages(&cx) {
+        Ok(manifests) => manifests,
+        Err(errors) => {
+            diagnostics.extend(errors.iter().cloned());
+            return Ok(vec![]);
+        }
+    };
+
+    let members = manifests.keys().cloned().collect::<Vec<_>>();
+    let default_members = rules
+        .keys()
+        .filter(|target| requested.iter().any(|pattern| pattern.matches(target)))
+        .filter_map(|target| cx.package_dir(target))
+        .collect::<BTreeSet<_>>();
+    let workspace = translate_workspace(&members, default_members);
+    let (profiles, warnings) = translate_profiles(&cx, &manifests);
+    diagnostics.extend(warnings);
+    // The Buck root can be a package itself, in which case it also has to
+    // serve as the workspace root
+    let root = manifests.entry(PathBuf::new()).or_default();
+    root.workspace = Some(workspace);
+    root.profiles = profiles;
+
+    let read_existing = |path: &Path| match fs::read_to_string(out_root.join(path)) {
+        Ok(existing) => Ok(Some(existing)),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    };
+
+    let mut files = vec![];
+    for (pkg_dir, manifest) in manifests {
+        let path = pkg_dir.join("Cargo.toml");
+        let existing = read_existing(&path)?;
+        let conflicts =
+            manifest.conflicting_profile_settings(existing.as_deref().unwrap_or_default());
+        if !conflicts.is_empty() {
+            let message = format!(
+                "Profile settings {} of {} differ from the ones translated from rustc_flags, \
+                 so they are left intact",
+                conflicts.join(", "),
+                path.display()
+            );
+            diagnostics.push(
+                Diagnostic::warning("conflicting-profile-settings", message)
+                    .with_base_path(&pkg_dir)
+                    .with_suggestion("Update the settings by hand if they are stale"),
+            );
+        }
+        let contents = match manifest.merge_into(existing.as_deref().unwrap_or_default()) {
+            Ok(contents) => contents,
+            Err(err) => {
+                let message = format!("Couldn't parse {}: {}", path.display(), err);
+                diagnostics.push(
+                    Diagnostic::error("invalid-manifest", message)
+                        .with_base_path(&pkg_dir)
+                        .with_suggestion("Fix the syntax errors or remove the manifest"),
+                );
+                continue;
+            }
+        };
+
+        files.push(OutputFile {
+            path,
+            existing,
+            contents,
+        });
+
+        if let Some(build_script) = manifest.build_script {
+            let path = pkg_dir.join(build_script::FILE_NAME);
+            let existing = read_existing(&path)?;
+            let generated = match &existing {
+                Some(existing) => existing
+                    .lines()
+                    .any(|line| line.trim() == build_script::GENERATED_MARKER),
+                None => true,
+            };
+
+            if generated {
+                files.push(OutputFile {
+                    path,
+                    existing,
+                    contents: build_script.to_string(),
+                });
+            } else {
+                let message = format!(
+                    "Build script {} is not generated, so it's left intact",
+                    path.display()
+                );
+                let instructions = build_script.instructions().join(", ");
+                diagnostics.push(
+                    Diagnostic::warning("hand-written-build-script", message)
+                        .with_base_path(&pkg_dir)
+                        .with_suggestion(format!("Make it print {}", instructions)),
+                );
+            }
+        }
+    }
+
+    Ok(files)
+}
+
+/// Generates a workspace with given package directories (relative to the Buck
+/// root) as members.
+pub fn translate_workspace<'a>(
+    members: impl IntoIterator<Item = &'a PathBuf>,
+    default_members: impl IntoIterator<Item = &'a Path>,
+) -> Workspace {
+    Workspace {
+        members: members.into_iter().cloned().collect(),
+        default_members: default_members.into_iter().map(Path::to_owned).collect(),
+    }
+}
+
+/// Translates the codegen options set in `rustc_flags` of the rules into
+/// profiles of the workspace root manifest, as Cargo ignores the profiles of
+/// member packages.
+///
+/// Most of the settings are overridden for single packages, but some are shared
+/// by the whole workspace. Either way, rules which end up sharing a setting may
+/// not agree on it, in which case the value set by the first one is used and a
+/// warning is returned.
+///
+/// Buck passes `rustc_flags` to rustc whatever the build mode is, but settings
+/// such as `opt-level = 3` would defeat the purpose of the `dev` profile. So
+/// every setting goes into the `release` profile, whereas the `dev` one only
+/// gets the `DEV_PROFILE_SETTINGS`, which the code may rely on.
+fn translate_profiles(
+    cx: &Context,
+    manifests: &BTreeMap<PathBuf, Manifest>,
+) -> (BTreeMap<String, Profile>, Vec<Diagnostic>) {
+    let mut profile = Profile::default();
+    let mut warnings = vec![];
+
+    let packages = packages(cx);
+    let all_rules = packages.iter().flat_map(|package| &package.rules);
+    let (settings, conflicting) = shared_profile_settings(all_rules, true);
+    profile.settings = settings;
+    if !conflicting.is_empty() {
+        let message = format!(
+            "Rules set different codegen options {}, which are shared by every package in a \
+             Cargo workspace, so the ones of the first rule setting them are used",
+            conflicting.join(", ")
+        );
+        warnings.push(
+            Diagnostic::warning("conflicting-codegen-flags", message)
+                .with_suggestion("Set the same codegen options in every rule"),
+        );
+    }
+
+    for package in &packages {
+        let (settings, conflicting) = shared_profile_settings(&package.rules, false);
+        if !conflicting.is_empty() {
+            let message = format!(
+                "Rules translated into package {} set different codegen options {}, so the \
+                 ones of the first rule setting them are used for the whole package",
+                package.dir.display(),
+                conflicting.join(", ")
+            );
+            warnings.push(
+                Diagnostic::warning("conflicting-codegen-flags", message)
+                    .with_base_path(package.dir)
+                    .with_suggestion("Set the same codegen options in every rule of the package"),
+            );
+        }
+
+        let name = manifests[package.dir]
+            .package
+            .as_ref()
+            .map(|p| p.name.clone());
+        if let (Some(name), false) = (name, settings.is_empty()) {
+            profile.packages.insert(name, settings);
+        }
+    }
+
+    let mut dev = profile.clone();
+    dev.settings
+        .retain(|key, _| DEV_PROFILE_SETTINGS.contains(&key.as_str()));
+    for settings in dev.packages.values_mut() {
+        settings.retain(|key, _| DEV_PROFILE_SETTINGS.contains(&key.as_str()));
+    }
+    dev.packages.retain(|_, settings| !settings.is_empty());
+
+    let profiles = vec![("dev", dev), ("release", profile)]
+        .into_iter()
+        .filter(|(_, profile)| *profile != Profile::default())
+        .map(|(name, profile)| (name.to_owned(), profile));
+    (profiles.collect(), warnings)
+}
+
+/// Collects the profile settings (either the workspace ones or the others)
+/// of the rules, along with the ones which the rules don't agree on.
+fn shared_profile_settings<'a>(
+    rules: impl IntoIterator<Item = &'a (&'a BuildTarget, &'a BuildRule)>,
+    workspace: bool,
+) -> (BTreeMap<String, ProfileValue>, Vec<&'static str>) {
+    let rule_settings = rules
+        .into_iter()
+        .map(|(_, rule)| {
+            let (settings, _) = profile_settings(rule);
+            settings
+                .into_iter()
+                .filter(|(key, _)| WORKSPACE_PROFILE_SETTINGS.contains(key) == workspace)
+                .collect::<BTreeMap<_, _>>()
+        })
+        .collect::<Vec<_>>();
+
+    let mut settings = BTreeMap::new();
+    let mut conflicting = vec![];
+    let keys = rule_settings.iter().flat_map(BTreeMap::keys);
+    for &key in keys.collect::<BTreeSet<_>>() {
+        // Rules which don't set it don't care about its value
+        let values = rule_settings
+            .iter()
+            .filter_map(|settings| settings.get(key));
+        let values = values.collect::<Vec<_>>();
+        if values.iter().any(|value| *value != values[0]) {
+            conflicting.push(key);
+        }
+        settings.insert(key.to_owned(), values[0].clone());
+    }
+
+    (settings, conflicting)
+}
+
+/// Translates the codegen options of the rule into profile settings. Returns
+/// them along with the flags which have no Cargo equivalent.
+fn profile_settings(rule: &BuildRule) -> (BTreeMap<&'static str, ProfileValue>, Vec<String>) {
+    let flags = RustcFlags::parse(rule.typ.rustc_flags());
+    let mut settings = BTreeMap::new();
+    let mut unsupported = vec![];
+    for (key, value) in flags.codegen {
+        match profile_setting(&key, value.as_deref()) {
+            Some((key, value)) => {
+                settings.insert(key, value);
+            }
+            None => unsupported.push(match value {
+                Some(value) => format!("-C {}={}", key, value),
+                None => format!("-C {}", key),
+            }),
+        }
+    }
+    unsupported.extend(flags.other);
+
+    (settings, unsupported)
+}
+
+/// Translates a codegen option into the equivalent profile setting, if there
+/// is one.
+fn profile_setting(key: &str, value: Option<&str>) -> Option<(&'static str, ProfileValue)> {
+    use ProfileValue::{Bool, Integer};
+    const DEBUG_LEVELS: &[&str] = &[
+        "none",
+        "limited",
+        "full",
+        "line-directives-only",
+        "line-tables-only",
+    ];
+    let string = |value: &str| ProfileValue::String(value.to_owned());
+    let boolean = |value: Option<&str>| match value {
+        None | Some("y" | "yes" | "on" | "true") => Some(true),
+        Some("n" | "no" | "off" | "false") => Some(false),
+        _ => None,
+    };
+
+    let setting = match (key, value) {
+        ("opt-level", Some(level @ ("s" | "z"))) => ("opt-level", string(level)),
+        ("opt-level", Some(level @ ("0" | "1" | "2" | "3"))) => {
+            ("opt-level", Integer(level.parse().unwrap()))
+        }
+        ("debuginfo", Some(level @ ("0" | "1" | "2"))) => {
+            ("debug", Integer(level.parse().unwrap()))
+        }
+        ("debuginfo", Some(level)) if DEBUG_LEVELS.contains(&level) => ("debug", string(level)),
+        ("panic", Some(strategy @ ("abort" | "unwind"))) => ("panic", string(strategy)),
+        ("lto", Some(lto @ ("fat" | "thin"))) => ("lto", string(lto)),
+        ("lto", value) => match boolean(value)? {
+            true => ("lto", Bool(true)),
+            false => ("lto", string("off")),
+        },
+        ("codegen-units", Some(units)) => ("codegen-units", Integer(units.parse().ok()?)),
+        ("debug-assertions", value) => ("debug-assertions", Bool(boolean(value)?)),
+        ("overflow-checks", value) => ("overflow-checks", Bool(boolean(value)?)),
+        ("rpath", value) => ("rpath", Bool(boolean(value)?)),
+        ("strip", Some(strip @ ("none" | "debuginfo" | "symbols"))) => ("strip", string(strip)),
+        _ => return None,
+    };
+    Some(setting)
+}
+
+/// Checks that every flag in `rustc_flags` of the rules is translated.
+///
+/// Returns a warning for every rule with flags which have no Cargo equivalent.
+fn check_rustc_flags(cx: &Context) -> Vec<Diagnostic> {
+    let mut warnings = vec![];
+
+    for package in packages(cx) {
+        for (target, rule) in package.rules {
+            let (_, unsupported) = profile_settings(rule);
+            if unsupported.is_empty() {
+                continue;
+            }
+
+            let message = format!(
+                "Rustc flags {} of {} have no Cargo equivalent, so they are ignored",
+                unsupported.join(" "),
+                target
+            );
+            warnings.push(
+                Diagnostic::warning("unsupported-rustc-flags", message)
+                    .with_target(target, &rule.base_path)
+                    .with_suggestion("Pass them with RUSTFLAGS instead"),
+            );
+        }
+    }
+
+    warnings
+}
+
+/// Translates every supported rule into Cargo.toml manifests.
+///
+/// Returns every generated manifest, keyed by the directory of its package
+/// (relative to the Buck root), or the errors of every package which couldn't
+/// be translated.
+pub fn translate_packages(cx: &Context) -> Result<BTreeMap<PathBuf, Manifest>, Diagnostics> {
+    let mut manifests = BTreeMap::new();
+    let mut errors = Diagnostics::default();
+    for package in packages(cx) {
+        match translate_package(&package, cx) {
+            Ok(manifest) => {
+                manifests.insert(package.dir.to_owned(), manifest);
+            }
+            Err(error) => errors.push(*error),
+        }
+    }
+
+    if errors.has_errors() {
+        Err(errors)
+    } else {
+        Ok(manifests)
+    }
+}
+
+/// Groups the supported rules into the packages they are translated into.
+fn packages<'a>(cx: &'a Context) -> Vec<Package<'a>> {
+    let mut packages = BTreeMap::<_, Package>::new();
+    for (target, rule) in cx.rules {
+        if let Some(pkg_dir) = cx.package_dir(target) {
+            packages
+                .entry(pkg_dir)
+                .or_insert_with(|| Package {
+                    dir: pkg_dir,
+                    rules: vec![],
+                })
+                .rules
+                .push((target, rule));
+        }
+    }
+
+    let mut packages = packages.into_values().collect::<Vec<_>>();
+    for package in &mut packages {
+        package.rules.sort_by_key(|(target, _)| *target); // deterministic
+    }
+    packages
+}
+
+fn translate_package(package: &Package, cx: &Context) -> Result<Manifest, Box<Diagnostic>> {
+    let dir = package.dir;
+    let rules = |predicate: fn(&BuildRule) -> bool| {
+        let rules = package.rules.iter().filter(|(_, r)| predicate(r));
+        rules.copied().collect::<Vec<_>>()
+    };
+    let libs = rules(|r| r.typ.is_library());
+    let bins = rules(|r| r.typ.is_binary() && !r.typ.is_test());
+    let tests = rules(|r| r.typ.is_test());
+    debug_assert!(libs.len() <= 1);
+
+    let default_bin = || {
+        bins.iter().find(|(_, b)| {
+            b.typ.crate_root().and_then(Path::file_name) == Some(OsStr::new("main.rs"))
+        })
+    };
+    let default_rule = libs
+        .first()
+        .or_else(default_bin)
+        .or_else(|| bins.first())
+        .or_else(|| tests.first())
+        .map(|(_, rule)| rule);
+    let default_rule = default_rule.ok_or_else(|| {
+        Diagnostic::error(
+            "no-default-rule",
+            format!(
+                "Couldn't find a fitting default Rule for package {}",
+                dir.display()
+            ),
+        )
+        .with_base_path(dir)
+    })?;
+
+    let mut package_section = manifest::Package::new(default_rule.typ.krate().unwrap());
+    let build_script = translate_build_script(package)?;
+    if build_script.is_some() {
+        package_section.build = Some(PathBuf::from(build_script::FILE_NAME));
+    }
+    match translate_license(package, cx)? {
+        Some(License::Spdx(expr)) => package_section.license = Some(expr),
+        Some(License::File(path)) => package_section.license_file = Some(path),
+        None => {}
+    }
+
+    let targets = |rules: Vec<(&BuildTarget, &BuildRule)>| {
+        rules
+            .into_iter()
+            .map(|(target, rule)| {
+                Ok(Target {
+                    harness: rule.typ.test_framework().filter(|framework| !framework),
+                    proc_macro: Some(true).filter(|_| rule.typ.is_proc_macro()),
+                    ..Target::new(
+                        rule.typ.krate().unwrap(),
+                        cx.crate_root_path(dir, target, rule)?,
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, Box<Diagnostic>>>()
+    };
+
+    let (dependencies, dev_dependencies) = translate_deps(package, cx)?;
+
+    let mut features = BTreeMap::new();
+    let enabled = translate_features(package)?;
+    if !enabled.is_empty() {
+        // Buck rules commonly enable a `default` feature too, which Cargo
+        // already defines as the one enabling the others
+        let others = enabled.into_iter().filter(|feature| feature != "default");
+        let others = others.collect::<Vec<_>>();
+        features.insert(String::from("default"), others.clone());
+        features.extend(others.into_iter().map(|feature| (feature, vec![])));
+    }
+
+    Ok(Manifest {
+        package: Some(package_section),
+        lib: targets(libs)?.pop(),
+        bins: targets(bins)?,
+        tests: targets(tests)?,
+        dependencies,
+        dev_dependencies,
+        features,
+        lints: translate_lints(package).0,
+        workspace: None,
+        profiles: BTreeMap::new(),
+        build_script,
+    })
+}
+
+/// Collects features enabled for the package.
+///
+/// Buck enables features per rule, whereas in Cargo these are shared by every
+/// target in a package, so the rules translated into a single package must
+/// agree on them. These are all enabled by default, like they are under Buck.
+fn translate_features(package: &Package) -> Result<BTreeSet<String>, Box<Diagnostic>> {
+    let mut features: Option<(&BuildTarget, BTreeSet<String>)> = None;
+
+    for (target, rule) in &package.rules {
+        let rule_features = rule.typ.features().iter().cloned().collect();
+        match features {
+            Some((first, ref first_features)) if *first_features != rule_features => {
+                let format_set =
+                    |set: &BTreeSet<String>| set.iter().cloned().collect::<Vec<_>>().join(", ");
+                let message = format!(
+                    "Rules {} ([{}]) and {} ([{}]) translated into package {} enable different \
+                     features, which are shared by every target in a Cargo package",
+                    first,
+                    format_set(first_features),
+                    target,
+                    format_set(&rule_features),
+                    package.dir.display()
+                );
+                let error = Diagnostic::error("conflicting-features", message)
+                    .with_target(target, &rule.base_path)
+                    .with_suggestion(
+                        "Enable the same features in every rule of the package or move the \
+                         rules into separate buildfiles",
+                    );
+                return Err(Box::new(error));
+            }
+            Some(..) => {}
+            None => features = Some((target, rule_features)),
+        }
+    }
+
+    Ok(features.map(|(_, features)| features).unwrap_or_default())
+}
+
+/// Lints of a package, keyed by the tool and the lint name.
+type Lints = BTreeMap<String, BTreeMap<String, manifest::Lint>>;
+
+/// Translates the lint levels set in `rustc_flags` of the rules.
+///
+/// Lint levels are shared by every target in a Cargo package, so if the rules
+/// translated into a single package don't agree on the level of a lint, the
+/// strictest one is used. Rules which don't set a lint count as using its
+/// default level. Such lints are returned as well.
+fn translate_lints(package: &Package) -> (Lints, Vec<LintName>) {
+    let rule_flags = package
+        .rules
+        .iter()
+        .map(|(_, rule)| {
+            let flags = RustcFlags::parse(rule.typ.rustc_flags());
+            let mut lints = flags.lints.into_iter().collect::<BTreeMap<_, _>>();
+            if let Some(cap) = flags.cap_lints {
+                for level in lints.values_mut() {
+                    *level = (*level).min(cap);
+                }
+                if cap == LintLevel::Allow {
+                    lints.insert(LintName::parse("warnings"), LintLevel::Allow);
+                }
+            }
+            (lints, flags.cap_lints)
+        })
+        .collect::<Vec<_>>();
+
+    let mut lints = Lints::new();
+    let mut conflicting = vec![];
+    let names = rule_flags.iter().flat_map(|(lints, _)| lints.keys());
+    for name in names.collect::<BTreeSet<_>>() {
+        let levels = rule_flags.iter().map(|(lints, cap)| match lints.get(name) {
+            Some(level) => *level,
+            None => cap.map_or(name.default_level(), |cap| name.default_level().min(cap)),
+        });
+        let levels = levels.collect::<Vec<_>>();
+        if levels.iter().any(|level| *level != levels[0]) {
+            conflicting.push(name.clone());
+        }
+
+        let lint = manifest::Lint {
+            level: levels.into_iter().max().unwrap(),
+            // Single lints override the lint groups they belong to
+            priority: if name.is_group() { -1 } else { 0 },
+        };
+        lints
+            .entry(name.tool.clone())
+            .or_default()
+            .insert(name.name.clone(), lint);
+    }
+
+    (lints, conflicting)
+}
+
+/// Checks that the rules translated into each package agree on lint levels.
+///
+/// Returns a warning for every package where they don't.
+fn check_lints(cx: &Context) -> Vec<Diagnostic> {
+    let mut warnings = vec![];
+
+    for package in packages(cx) {
+        let (_, conflicting) = translate_lints(&package);
+        if conflicting.is_empty() {
+            continue;
+        }
+
+        let conflicting = conflicting.iter().map(ToString::to_string);
+        let message = format!(
+            "Rules translated into package {} set different levels of lints {}, so the \
+             strictest ones are used for the whole package",
+            package.dir.display(),
+            conflicting.collect::<Vec<_>>().join(", ")
+        );
+        warnings.push(
+            Diagnostic::warning("conflicting-lints", message)
+                .with_base_path(package.dir)
+                .with_suggestion("Set the same lint levels in every rule of the package"),
+        );
+    }
+
+    warnings
+}
+
+/// Generates a build script passing the linker flags and the cfgs of the rules
+/// to rustc, if any of the rules has them.
+///
+/// Cargo can pass linker arguments to a single binary, but only to every test
+/// of a package at once, so the tests translated into a single package must
+/// agree on them.
+fn translate_build_script(package: &Package) -> Result<Option<BuildScript>, Box<Diagnostic>> {
+    let bins = package
+        .rules
+        .iter()
+        .filter(|(_, rule)| rule.typ.is_binary() && !rule.typ.is_test())
+        .map(|(_, rule)| (rule.typ.krate().unwrap(), rule.typ.linker_flags()))
+        .collect::<Vec<_>>();
+
+    let mut tests_flags: Option<(&BuildTarget, &[String])> = None;
+    for (target, rule) in package.rules.iter().filter(|(_, r)| r.typ.is_test()) {
+        let flags = rule.typ.linker_flags();
+        match tests_flags {
+            Some((first, first_flags)) if first_flags != flags => {
+                let message = format!(
+                    "Tests {} ([{}]) and {} ([{}]) translated into package {} have different \
+                     linker flags, which are shared by every test in a Cargo package",
+                    first,
+                    first_flags.join(", "),
+                    target,
+                    flags.join(", "),
+                    package.dir.display()
+                );
+                let error = Diagnostic::error("conflicting-linker-flags", message)
+                    .with_target(target, &rule.base_path)
+                    .with_suggestion(
+                        "Use the same linker flags for every test of the package or move the \
+                         tests into separate buildfiles",
+                    );
+                return Err(Box::new(error));
+            }
+            Some(..) => {}
+            None => tests_flags = Some((target, flags)),
+        }
+    }
+    let tests_flags = tests_flags.map(|(_, flags)| flags);
+
+    let mut script = BuildScript::default();
+    let shared_bins_flags = bins
+        .first()
+        .map(|&(_, flags)| flags)
+        .filter(|&flags| bins.iter().all(|&(_, bin_flags)| bin_flags == flags));
+    match (shared_bins_flags, tests_flags) {
+        (Some(bins_flags), Some(tests_flags)) if bins_flags == tests_flags => {
+            script.link_args = bins_flags.to_vec();
+        }
+        _ => {
+            match shared_bins_flags {
+                Some(flags) => script.bins_link_args = flags.to_vec(),
+                None => {
+                    for (bin, flags) in bins.into_iter().filter(|(_, f)| !f.is_empty()) {
+                        script.bin_link_args.insert(bin.to_owned(), flags.to_vec());
+                    }
+                }
+            }
+            script.tests_link_args = tests_flags.unwrap_or_default().to_vec();
+        }
+    }
+    script.cfgs = translate_cfgs(package)?;
+
+    Ok(Some(script).filter(|script| !script.is_empty()))
+}
+
+/// Translates the configuration options set with `--cfg` in `rustc_flags`.
+///
+/// Build scripts set them for every target of a package, so all the rules
+/// translated into a single package must agree on them.
+fn translate_cfgs(package: &Package) -> Result<Vec<Cfg>, Box<Diagnostic>> {
+    let mut cfgs: Option<(&BuildTarget, BTreeSet<Cfg>)> = None;
+    for (target, rule) in &package.rules {
+        let rule_cfgs = RustcFlags::parse(rule.typ.rustc_flags()).cfgs;
+        let rule_cfgs = rule_cfgs.into_iter().collect::<BTreeSet<_>>();
+        match &cfgs {
+            Some((first, first_cfgs)) if *first_cfgs != rule_cfgs => {
+                let format_cfgs = |cfgs: &BTreeSet<Cfg>| {
+                    let cfgs = cfgs.iter().map(ToString::to_string);
+                    cfgs.collect::<Vec<_>>().join(", ")
+                };
+                let message = format!(
+                    "Rules {} ([{}]) and {} ([{}]) translated into package {} set different \
+                     cfgs, which are shared by every target in a Cargo package",
+                    first,
+                    format_cfgs(first_cfgs),
+                    target,
+                    format_cfgs(&rule_cfgs),
+                    package.dir.display()
+                );
+                let error = Diagnostic::error("conflicting-cfgs", message)
+                    .with_target(target, &rule.base_path)
+                    .with_suggestion(
+                        "Set the same cfgs in every rule of the package or move the rules \
+                         into separate buildfiles",
+                    );
+                return Err(Box::new(error));
+            }
+            Some(..) => {}
+            None => cfgs = Some((target, rule_cfgs)),
+        }
+    }
+
+    let cfgs = cfgs.map(|(_, cfgs)| cfgs).unwrap_or_default();
+    Ok(cfgs.into_iter().collect())
+}
+
+/// License of a package, as specified in its manifest.
+#[derive(Debug, PartialEq)]
+enum License {
+    /// SPDX license expression.
+    Spdx(String),
+    /// Path to a nonstandard license file, relative to the package directory.
+    File(PathBuf),
+}
+
+/// Determines the license of the package from license files of its rules.
+///
+/// If every license file of a rule is recognized as a well-known license, their
+/// SPDX expression is used, unless it's ambiguous whether they apply together
+/// or as alternatives. Otherwise the single license file is referred to
+/// directly, since Cargo supports only one.
+fn translate_license(package: &Package, cx: &Context) -> Result<Option<License>, Box<Diagnostic>> {
+    let mut license: Option<(&BuildTarget, License)> = None;
+
+    for (target, rule) in &package.rules {
+        let files = rule.typ.licenses();
+        if files.is_empty() {
+            continue;
+        }
+
+        let spdx_ids = files
+            .iter()
+            .map(|file| {
+                let path = cx.buck_root.join(&rule.base_path).join(file);
+                let text = fs::read_to_string(path).ok()?;
+                license::identify(&text)
+            })
+            .collect::<Option<BTreeSet<_>>>();
+
+        let rule_license = match (spdx_ids, files) {
+            (Some(ids), _) => match license::expression(&ids) {
+                Some(expr) => License::Spdx(expr),
+                None => {
+                    let ids = ids.into_iter().collect::<Vec<_>>();
+                    let message = format!(
+                        "Rule {} has multiple license files with well-known licenses ({}), \
+                         but it's unclear whether all of them apply or any one of them",
+                        target,
+                        ids.join(", ")
+                    );
+                    let error = Diagnostic::error("ambiguous-licenses", message)
+                        .with_target(target, &rule.base_path)
+                        .with_suggestion(
+                            "List only the license files which apply to the rule, or the one \
+                             chosen for the package if they are alternatives",
+                        );
+                    return Err(Box::new(error));
+                }
+            },
+            (None, [file]) => License::File(cx.source_path(package.dir, &rule.base_path, file)),
+            (None, _) => {
+                let message = format!(
+                    "Rule {} has multiple license files ({}), not all of them with a \
+                     well-known license, but Cargo supports only a single license-file",
+                    target,
+                    files.join(", ")
+                );
+                let error = Diagnostic::error("multiple-license-files", message)
+                    .with_target(target, &rule.base_path)
+                    .with_suggestion("Combine the license files into a single one");
+                return Err(Box::new(error));
+            }
+        };
+
+        match license {
+            Some((first, ref first_license)) if *first_license != rule_license => {
+                let message = format!(
+                    "Rules {} ({:?}) and {} ({:?}) translated into package {} have \
+                     conflicting licenses",
+                    first,
+                    first_license,
+                    target,
+                    rule_license,
+                    package.dir.display()
+                );
+                let error = Diagnostic::error("conflicting-licenses", message)
+                    .with_target(target, &rule.base_path)
+                    .with_suggestion(
+                        "Use the same license files in every rule of the package or move the \
+                         rules into separate buildfiles",
+                    );
+                return Err(Box::new(error));
+            }
+            Some(..) => {}
+            None => license = Some((target, rule_license)),
+        }
+    }
+
+    Ok(license.map(|(_, license)| license))
+}
+
+/// Checks that tests not using the standard test framework define their own
+/// `main`, since otherwise they fail to link with `harness = false` in Cargo.
+///
+/// Returns a warning for every offending test.
+fn check_custom_harnesses(buck_root: &Path, rules: &Rules) -> Vec<Diagnostic> {
+    let mut warnings = vec![];
+
+    for (target, rule) in rules {
+        if rule.typ.test_framework() != Some(false) {
+            continue;
+        }
+
+        // Rules without a crate root fail to translate anyway
+        let Some(crate_root) = rule.typ.crate_root() else {
+            continue;
+        };
+        let crate_root = buck_root.join(&rule.base_path).join(crate_root);
+        let warning = match fs::read_to_string(&crate_root) {
+            Ok(ref source) if has_main_fn(source) => continue,
+            Ok(..) => Diagnostic::warning(
+                "missing-main",
+                format!(
+                    "Test {} doesn't use the test framework but its crate root {} has no \
+                     `fn main`",
+                    target,
+                    crate_root.display()
+                ),
+            )
+            .with_suggestion("Define `fn main` or use the standard test framework"),
+            Err(err) => Diagnostic::warning(
+                "missing-main",
+                format!(
+                    "Couldn't check crate root {} of test {} for `fn main`: {}",
+                    crate_root.display(),
+                    target,
+                    err
+                ),
+            ),
+        };
+        warnings.push(warning.with_target(target, &rule.base_path));
+    }
+
+    warnings.sort_by(|a, b| a.target.cmp(&b.target));
+    warnings
+}
+
+/// Checks that the dependencies between translated rules are allowed by the
+/// visibility of the dependencies. Rules are always visible to the other ones
+/// in the same buildfile.
+///
+/// Returns a warning for every disallowed dependency.
+fn check_visibility(cx: &Context) -> Vec<Diagnostic> {
+    let mut warnings = vec![];
+
+    for (target, rule) in cx.rules {
+        if cx.package_dir(target).is_none() {
+            continue;
+        }
+
+        for dep in &rule.common.deps {
+            let dep = dep.unflavored();
+            let dep_rule = match cx.rules.get(&dep) {
+                Some(dep_rule) if cx.package_dir(&dep).is_some() => dep_rule,
+                _ => continue,
+            };
+
+            let same_buildfile =
+                dep.cell() == target.cell() && dep.base_path() == target.base_path();
+            let visible = dep_rule.common.visibility.iter().any(|p| p.matches(target));
+            if !same_buildfile && !visible {
+                let message = format!("{} depends on {}, which is not visible to it", target, dep);
+                warnings.push(
+                    Diagnostic::warning("not-visible", message)
+                        .with_target(target, &rule.base_path)
+                        .with_suggestion(format!("Add {} to the visibility of {}", target, dep)),
+                );
+            }
+        }
+    }
+
+    warnings.sort_by(|a, b| (&a.target, &a.message).cmp(&(&b.target, &b.message)));
+    warnings
+}
+
+/// Packages which (transitively) depend on each other.
+struct PackageCycle<'a> {
+    /// Directories of the packages, relative to the Buck root.
+    packages: Vec<PathBuf>,
+    /// Dependencies between rules of the packages, which make up the cycle.
+    deps: Vec<(&'a BuildTarget, &'a BuildTarget)>,
+}
+
+/// Finds the dependency cycles between the translated packages. Tests are
+/// skipped, since Cargo allows cycles through dev-dependencies.
+fn package_cycles<'a>(cx: &Context<'a>) -> Vec<PackageCycle<'a>> {
+    let rules: &'a Rules = cx.rules;
+    let dep_graph = graph::dep_graph(rules);
+
+    let mut package_graph = DiGraphMap::<&Path, ()>::new();
+    let mut package_deps = vec![];
+    for (target, dep, _) in dep_graph.all_edges() {
+        let (dep, dep_rule) = match rules.get_key_value(&dep.unflavored()) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        if rules[target].typ.is_test() || !dep_rule.typ.is_library() {
+            continue;
+        }
+
+        match (cx.package_dir(target), cx.package_dir(dep)) {
+            (Some(from), Some(to)) if from != to => {
+                package_graph.add_edge(from, to, ());
+                package_deps.push((from, to, (target, dep)));
+            }
+            _ => {}
+        }
+    }
+
+    let mut cycles = vec![];
+    for mut packages in tarjan_scc(&package_graph) {
+        if packages.len() < 2 {
+            continue;
+        }
+        packages.sort();
+
+        let mut deps = package_deps
+            .iter()
+            .filter(|(from, to, _)| packages.contains(from) && packages.contains(to))
+            .map(|&(_, _, dep)| dep)
+            .collect::<Vec<_>>();
+        deps.sort();
+
+        cycles.push(PackageCycle {
+            packages: packages.into_iter().map(Path::to_owned).collect(),
+            deps,
+        });
+    }
+
+    cycles.sort_by(|a, b| a.packages.cmp(&b.packages));
+    cycles
+}
+
+/// Returns an error for every dependency cycle between the packages.
+fn check_package_cycles(cx: &Context) -> Vec<Diagnostic> {
+    package_cycles(cx)
+        .into_iter()
+        .map(|cycle| {
+            let packages = cycle.packages.iter().map(|dir| dir.display().to_string());
+            let deps = cycle
+                .deps
+                .iter()
+                .map(|(target, dep)| format!("{} -> {}", target, dep));
+            let message = format!(
+                "Packages {} depend on each other through {}",
+                packages.collect::<Vec<_>>().join(", "),
+                deps.collect::<Vec<_>>().join(", ")
+            );
+
+            Diagnostic::error("package-cycle", message)
+                .with_base_path(&cycle.packages[0])
+                .with_suggestion("Use --split-cycles to move the binaries into separate packages")
+        })
+        .collect()
+}
+
+/// Reports every rule which can't be translated, as an error unless
+/// `keep_going`, in which case the translation leaves the rule out.
+pub fn check_unsupported_rules(rules: &Rules, keep_going: bool) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+
+    for (target, dependents) in unsupported_rules(rules) {
+        let rule = &rules[target];
+        let mut message = format!(
+            "Build target {} (of type {}) not supported",
+            target,
+            rule.typ.name()
+        );
+        if !dependents.is_empty() {
+            let dependents = dependents.iter().map(ToString::to_string);
+            message += &format!(
+                ", required by {}",
+                dependents.collect::<Vec<_>>().join(", ")
+            );
+        }
+
+        let diagnostic = if keep_going {
+            Diagnostic::warning("unsupported-rule", message)
+        } else {
+            Diagnostic::error("unsupported-rule", message)
+                .with_suggestion("Use --keep-going to translate the other rules without it")
+        };
+        diagnostics.push(diagnostic.with_target(target, &rule.base_path));
+    }
+
+    diagnostics
+}
+
+/// Returns the rules which can't be translated, each with the supported rules
+/// which depend on it, either directly or through other unsupported rules.
+///
+/// Translated packages don't depend on the unsupported rules at all.
+fn unsupported_rules(rules: &Rules) -> BTreeMap<&BuildTarget, Vec<&BuildTarget>> {
+    let mut unsupported = rules
+        .iter()
+        .filter(|(_, rule)| !rule.typ.is_supported())
+        .map(|(target, _)| (target, vec![]))
+        .collect::<BTreeMap<_, _>>();
+
+    for (target, rule) in rules.iter().filter(|(_, r)| r.typ.is_supported()) {
+        let mut visited = BTreeSet::new();
+        let mut stack = rule.common.deps.iter().collect::<Vec<_>>();
+        while let Some(dep) = stack.pop() {
+            let (dep, dep_rule) = match rules.get_key_value(&dep.unflavored()) {
+                Some((dep, dep_rule)) if !dep_rule.typ.is_supported() => (dep, dep_rule),
+                _ => continue,
+            };
+            if visited.insert(dep) {
+                unsupported.get_mut(dep).unwrap().push(target);
+                stack.extend(&dep_rule.common.deps);
+            }
+        }
+    }
+
+    for dependents in unsupported.values_mut() {
+        dependents.sort();
+    }
+    unsupported
+}
+
+/// Returns whether the source code seems to define a `main` function.
+fn has_main_fn(source: &str) -> bool {
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+
+    source.match_indices("fn main").any(|(idx, pat)| {
+        let before = source[..idx].chars().next_back();
+        let after = source[idx + pat.len()..].chars().next();
+        !before.is_some_and(is_ident_char) && !after.is_some_and(is_ident_char)
+    })
+}
+
+/// Path dependencies of a package, keyed by crate name.
+type Dependencies = BTreeMap<String, Dependency>;
+
+/// Collects Cargo path dependencies and dev-dependencies (used only by the
+/// tests) for the package.
+///
+/// Only dependencies on Rust libraries translated into other packages are
+/// returned, since Cargo already makes the package's own [lib] available to
+/// its other targets.
+fn translate_deps(
+    package: &Package,
+    cx: &Context,
+) -> Result<(Dependencies, Dependencies), Box<Diagnostic>> {
+    let (mut deps, mut dev_deps) = (BTreeMap::new(), BTreeMap::new());
+
+    for (target, rule) in &package.rules {
+        let deps = if rule.typ.is_test() {
+            &mut dev_deps
+        } else {
+            &mut deps
+        };
+
+        for dep in &rule.common.deps {
+            // Flavored dependencies still refer to the same rule
+            let dep = &dep.unflavored();
+            let dep_rule = cx.rules.get(dep).ok_or_else(|| {
+                let message = format!("Dependency {} of {} was not queried", dep, target);
+                Diagnostic::error("missing-dependency", message)
+                    .with_target(target, &rule.base_path)
+                    .with_suggestion("Query the dependencies of the rule as well, e.g. deps(...)")
+            })?;
+
+            if !dep_rule.typ.is_library() {
+                continue;
+            }
+
+            let dep_dir = match cx.package_dir(dep) {
+                Some(dep_dir) if dep_dir != package.dir => dep_dir,
+                _ => continue,
+            };
+
+            let name = dep_rule.typ.krate().unwrap().to_owned();
+            let path = relative_path(package.dir, dep_dir);
+            deps.insert(name, Dependency { path });
+        }
+    }
+    dev_deps.retain(|name, _| !deps.contains_key(name));
+
+    Ok((deps, dev_deps))
+}
+
+/// Returns a path that leads from `from` to `to` directory, where both are
+/// relative to the same root (e.g. a Buck base path).
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut path = PathBuf::new();
+    for _ in common..from.len() {
+        path.push("..");
+    }
+    for component in &to[common..] {
+        path.push(component);
+    }
+
+    if path.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(s: &str) -> BuildTarget {
+        s.parse().unwrap()
+    }
+
+    fn pattern(s: &str) -> BuildTargetPattern {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn translate_multiple_libs() -> Result<(), failure::Error> {
+        let input = r#"{
+            "//dir:lib1" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "name" : "lib1",
+                "srcs" : [ "src/lib.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//dir:lib2" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [ "//dir:lib1" ],
+                "name" : "lib2",
+                "srcs" : [ "lib2/lib.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//dir:bin" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [ "//dir:lib1", "//dir:lib2" ],
+                "name" : "bin",
+                "srcs" : [ "src/main.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            }
+        }"#;
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+
+        let cx = Context::new(Path::new(""), &all_rules);
+        // The binary would make the packages of the libraries depend on each
+        // other, so it gets a package of its own
+        assert!(check_package_cycles(&cx).is_empty());
+        let manifests = translate_packages(&cx)?;
+        assert_eq!(
+            manifests
+                .into_iter()
+                .map(|(dir, manifest)| (dir, manifest.to_string()))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    PathBuf::from("dir"),
+                    r#"[package]
+name = "lib1"
+version = "0.1.0"
+authors = ["Example <author@example.com>"]
+
+[lib]
+name = "lib1"
+path = "src/lib.rs"
+"#
+                    .to_owned()
+                ),
+                (
+                    PathBuf::from("dir/cargo-bins/bin"),
+                    r#"[package]
+name = "bin"
+version = "0.1.0"
+authors = ["Example <author@example.com>"]
+
+[[bin]]
+name = "bin"
+path = "../../src/main.rs"
+
+[dependencies]
+lib1 = { path = "../.." }
+lib2 = { path = "../../cargo-libs/lib2" }
+"#
+                    .to_owned()
+                ),
+                (
+                    PathBuf::from("dir/cargo-libs/lib2"),
+                    r#"[package]
+name = "lib2"
+version = "0.1.0"
+authors = ["Example <author@example.com>"]
+
+[lib]
+name = "lib2"
+path = "../../lib2/lib.rs"
+
+[dependencies]
+lib1 = { path = "../.." }
+"#
+                    .to_owned()
+                ),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn translate_pkg_name_with_lib() -> Result<(), failure::Error> {
+        let input = r#"{
+            "//dir:bin1" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [],
+                "name" : "bin1",
+                "srcs" : [ "src/main.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//dir:lib1" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "name" : "lib1",
+                "srcs" : [ "src/lib.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//dir:aux_bin" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [],
+                "name" : "aux_bin",
+                "srcs" : [ "aux_bin.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            }
+        }"#;
+
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new(""), &all_rules);
+        assert_eq!(
+            translate_packages(&cx)?[Path::new("dir")].to_string(),
+            r#"[package]
+name = "lib1"
+version = "0.1.0"
+authors = ["Example <author@example.com>"]
+
+[lib]
+name = "lib1"
+path = "src/lib.rs"
+
+[[bin]]
+name = "aux_bin"
+path = "aux_bin.rs"
+
+[[bin]]
+name = "bin1"
+path = "src/main.rs"
+"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn translate_pkg_name_with_bin() -> Result<(), failure::Error> {
+        let input = r#"{
+            "//dir:aux_bin" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [],
+                "name" : "aux_bin",
+                "srcs" : [ "aux_bin.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//dir:bin1" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [],
+                "name" : "bin1",
+                "srcs" : [ "src/main.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            }
+        }"#;
+
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new(""), &all_rules);
+        assert_eq!(
+            translate_packages(&cx)?[Path::new("dir")].to_string(),
+            r#"[package]
+name = "bin1"
+version = "0.1.0"
+authors = ["Example <author@example.com>"]
+
+[[bin]]
+name = "aux_bin"
+path = "aux_bin.rs"
+
+[[bin]]
+name = "bin1"
+path = "src/main.rs"
+"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn translate_lib() -> Result<(), failure::Error> {
+        let input = r#"{
+            "//dir:lib1" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "name" : "lib1",
+                "srcs" : [ "src/lib.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            }
+        }"#;
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new(""), &all_rules);
+        assert_eq!(
+            translate_packages(&cx)?[Path::new("dir")].to_string(),
+            r#"[package]
+name = "lib1"
+version = "0.1.0"
+authors = ["Example <author@example.com>"]
+
+[lib]
+name = "lib1"
+path = "src/lib.rs"
+"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn translate_proc_macro() -> Result<(), failure::Error> {
+        let input = r#"{
+            "//dir:macros" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "name" : "macros",
+                "proc_macro" : true,
+                "srcs" : [ "lib.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            }
+        }"#;
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new(""), &all_rules);
+        assert_eq!(
+            translate_packages(&cx)?[Path::new("dir")].to_string(),
+            r#"[package]
+name = "macros"
+version = "0.1.0"
+authors = ["Example <author@example.com>"]
+
+[lib]
+name = "macros"
+path = "lib.rs"
+proc-macro = true
+"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn translate_path_deps() -> Result<(), failure::Error> {
+        let input = r#"{
+            "//app:app" : {
+                "buck.base_path" : "app",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [ "//app:applib", "//common/util:util#check", "//third-party:cpp" ],
+                "name" : "app",
+                "srcs" : [ "main.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//app:applib" : {
+                "buck.base_path" : "app",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [ "//common/util" ],
+                "name" : "applib",
+                "srcs" : [ "lib.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//common/util:util" : {
+                "buck.base_path" : "common/util",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "crate" : "my_util",
+                "deps" : [],
+                "name" : "util",
+                "srcs" : [ "lib.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//third-party:cpp" : {
+                "buck.base_path" : "third-party",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "cxx_library",
+                "deps" : [],
+                "name" : "cpp",
+                "visibility" : [ "PUBLIC" ]
+            }
+        }"#;
+
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new(""), &all_rules);
+        assert_eq!(
+            translate_packages(&cx)?[Path::new("app")].to_string(),
+            r#"[package]
+name = "applib"
+version = "0.1.0"
+authors = ["Example <author@example.com>"]
+
+[lib]
+name = "applib"
+path = "lib.rs"
+
+[[bin]]
+name = "app"
+path = "main.rs"
+
+[dependencies]
+my_util = { path = "../common/util" }
+"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn relative_paths() {
+        let rel = |from, to| relative_path(Path::new(from), Path::new(to));
+        assert_eq!(rel("a/b", "a/b"), Path::new("."));
+        assert_eq!(rel("a/b", "a/c"), Path::new("../c"));
+        assert_eq!(rel("a", "a/b/c"), Path::new("b/c"));
+        assert_eq!(rel("a/b/c", "d"), Path::new("../../../d"));
+        assert_eq!(rel("", "d/e"), Path::new("d/e"));
+        assert_eq!(rel("d/e", ""), Path::new("../.."));
+    }
+
+    #[test]
+    fn translate_workspace_members() {
+        let members = vec![
+            PathBuf::from(""),
+            PathBuf::from("app"),
+            PathBuf::from("dir/cargo-libs/lib2"),
+        ];
+        let manifest = |workspace| Manifest {
+            workspace: Some(workspace),
+            ..Default::default()
+        };
+        assert_eq!(
+            manifest(translate_workspace(
+                &members,
+                vec![Path::new(""), Path::new("app")]
+            ))
+            .to_string(),
+            r#"[workspace]
+members = [
+    ".",
+    "app",
+    "dir/cargo-libs/lib2",
+]
+default-members = [
+    ".",
+    "app",
+]
+"#
+        );
+
+        assert_eq!(
+            manifest(translate_workspace(&members[1..], vec![])).to_string(),
+            r#"[workspace]
+members = [
+    "app",
+    "dir/cargo-libs/lib2",
+]
+"#
+        );
+    }
+
+    #[test]
+    fn translate_enabled_features() -> Result<(), failure::Error> {
+        let input = r#"{
+            "//dir:lib" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "features" : [ "foo", "bar" ],
+                "name" : "lib",
+                "srcs" : [ "lib.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//dir:bin" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [],
+                "features" : [ "bar", "foo" ],
+                "name" : "bin",
+                "srcs" : [ "main.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            }
+        }"#;
+
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new(""), &all_rules);
+        assert_eq!(
+            translate_packages(&cx)?[Path::new("dir")].to_string(),
+            r#"[package]
+name = "lib"
+version = "0.1.0"
+authors = ["Example <author@example.com>"]
+
+[lib]
+name = "lib"
+path = "lib.rs"
+
+[[bin]]
+name = "bin"
+path = "main.rs"
+
+[features]
+default = ["bar", "foo"]
+bar = []
+foo = []
+"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn translate_default_feature() -> Result<(), failure::Error> {
+        let input = r#"{
+            "//dir:lib" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "features" : [ "default", "foo" ],
+                "name" : "lib",
+                "srcs" : [ "lib.rs" ]
+            },
+            "//other:lib" : {
+                "buck.base_path" : "other",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "features" : [ "default" ],
+                "name" : "lib",
+                "srcs" : [ "lib.rs" ]
+            }
+        }"#;
+
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new(""), &all_rules);
+        let manifests = translate_packages(&cx)?;
+        let features = |dir: &str| manifests[Path::new(dir)].features.clone();
+        assert_eq!(
+            features("dir"),
+            vec![
+                (String::from("default"), vec![String::from("foo")]),
+                (String::from("foo"), vec![]),
+            ]
+            .into_iter()
+            .collect()
+        );
+        assert_eq!(
+            features("other"),
+            vec![(String::from("default"), vec![])]
+                .into_iter()
+                .collect()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_conflicting_features() {
+        let input = r#"{
+            "//dir:lib" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "features" : [ "foo" ],
+                "name" : "lib",
+                "srcs" : [ "lib.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//dir:bin" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [],
+                "name" : "bin",
+                "srcs" : [ "main.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            }
+        }"#;
+
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new(""), &all_rules);
+        let result = translate_packages(&cx);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn translate_tests() -> Result<(), failure::Error> {
+        let input = r#"{
+            "//lib:lib" : {
+                "buck.base_path" : "lib",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "name" : "lib",
+                "srcs" : [ "lib.rs" ],
+                "tests" : [ "//tests:lib_test" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//lib:smoke" : {
+                "buck.base_path" : "lib",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_test",
+                "deps" : [ "//lib:lib" ],
+                "name" : "smoke",
+                "srcs" : [ "smoke.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//tests:lib_test" : {
+                "buck.base_path" : "tests",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_test",
+                "deps" : [ "//lib:lib", "//util:util" ],
+                "name" : "lib_test",
+                "srcs" : [ "lib_test.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//util:util" : {
+                "buck.base_path" : "util",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "name" : "util",
+                "srcs" : [ "lib.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            }
+        }"#;
+
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new(""), &all_rules);
+        let manifests = translate_packages(&cx)?;
+        assert_eq!(
+            manifests.keys().collect::<Vec<_>>(),
+            vec![Path::new("lib"), Path::new("util")]
+        );
+        assert_eq!(
+            manifests[Path::new("lib")].to_string(),
+            r#"[package]
+name = "lib"
+version = "0.1.0"
+authors = ["Example <author@example.com>"]
+
+[lib]
+name = "lib"
+path = "lib.rs"
+
+[[test]]
+name = "smoke"
+path = "smoke.rs"
+
+[[test]]
+name = "lib_test"
+path = "../tests/lib_test.rs"
+
+[dev-dependencies]
+util = { path = "../util" }
+"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn translate_custom_harness() -> Result<(), failure::Error> {
+        let input = r#"{
+            "//dir:bench" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_test",
+                "deps" : [],
+                "framework" : false,
+                "name" : "bench",
+                "srcs" : [ "bench.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            }
+        }"#;
+
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new(""), &all_rules);
+        assert_eq!(
+            translate_packages(&cx)?[Path::new("dir")].to_string(),
+            r#"[package]
+name = "bench"
+version = "0.1.0"
+authors = ["Example <author@example.com>"]
+
+[[test]]
+name = "bench"
+path = "bench.rs"
+harness = false
+"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn detect_main_fn() {
+        assert!(has_main_fn("fn main() {}"));
+        assert!(has_main_fn("pub fn main() -> Result<(), ()> { Ok(()) }"));
+        assert!(has_main_fn("#[tokio::main]\nasync fn main () {}"));
+        assert!(!has_main_fn("fn main_loop() {}"));
+        assert!(!has_main_fn("fn domain() {}"));
+        assert!(!has_main_fn("#[test]\nfn it_works() {}"));
+    }
+
+    #[test]
+    fn translate_license_file() -> Result<(), failure::Error> {
+        let input = r#"{
+            "//dir:lib" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "licenses" : [ "LICENSE-CUSTOM" ],
+                "name" : "lib",
+                "srcs" : [ "lib.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//dir:bin" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [],
+                "name" : "bin",
+                "srcs" : [ "main.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            }
+        }"#;
+
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new("nonexistent"), &all_rules);
+        assert_eq!(
+            translate_packages(&cx)?[Path::new("dir")].to_string(),
+            r#"[package]
+name = "lib"
+version = "0.1.0"
+authors = ["Example <author@example.com>"]
+license-file = "LICENSE-CUSTOM"
+
+[lib]
+name = "lib"
+path = "lib.rs"
+
+[[bin]]
+name = "bin"
+path = "main.rs"
+"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_conflicting_licenses() {
+        let input = r#"{
+            "//dir:lib" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "licenses" : [ "LICENSE" ],
+                "name" : "lib",
+                "srcs" : [ "lib.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//dir:bin" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [],
+                "licenses" : [ "COPYING" ],
+                "name" : "bin",
+                "srcs" : [ "main.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            }
+        }"#;
+
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new("nonexistent"), &all_rules);
+        assert!(translate_packages(&cx).is_err());
+    }
+
+    #[test]
+    fn manifest_file_diff() {
+        let mut file = OutputFile {
+            path: PathBuf::from("dir/Cargo.toml"),
+            existing: Some(String::from("[package]\nname = \"old\"\n")),
+            contents: String::from("[package]\nname = \"new\"\n"),
+        };
+        assert!(file.is_changed());
+        assert_eq!(
+            file.diff(),
+            r#"--- a/dir/Cargo.toml
++++ b/dir/Cargo.toml
+@@ -1,2 +1,2 @@
+ [package]
+-name = "old"
++name = "new"
+"#
+        );
+
+        file.existing = None;
+        assert!(file.is_changed());
+        let diff = file.diff();
+        assert!(diff.starts_with("--- /dev/null\n+++ b/dir/Cargo.toml\n"));
+
+        file.existing = Some(file.contents.clone());
+        assert!(!file.is_changed());
+        assert_eq!(file.diff(), "");
+    }
+
+    #[test]
+    fn translate_out_of_tree() -> Result<(), failure::Error> {
+        let input = r#"{
+            "//dir:lib" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [ "//other:other" ],
+                "licenses" : [ "LICENSE" ],
+                "name" : "lib",
+                "srcs" : [ "src/lib.rs" ],
+                "tests" : [ "//tests:test" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//other:other" : {
+                "buck.base_path" : "other",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "name" : "other",
+                "srcs" : [ "lib.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//tests:test" : {
+                "buck.base_path" : "tests",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_test",
+                "deps" : [ "//dir:lib" ],
+                "name" : "test",
+                "srcs" : [ "test.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            }
+        }"#;
+
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let mut cx = Context::new(Path::new("nonexistent"), &all_rules);
+        cx.source_root = PathBuf::from("../repo");
+        assert_eq!(
+            translate_packages(&cx)?[Path::new("dir")].to_string(),
+            r#"[package]
+name = "lib"
+version = "0.1.0"
+authors = ["Example <author@example.com>"]
+license-file = "../../repo/dir/LICENSE"
+
+[lib]
+name = "lib"
+path = "../../repo/dir/src/lib.rs"
+
+[[test]]
+name = "test"
+path = "../../repo/tests/test.rs"
+
+[dependencies]
+other = { path = "../other" }
+"#
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn translate_with_backend() -> Result<(), failure::Error> {
+        let input = r#"{
+            "//bin:bin" : {
+                "buck.base_path" : "bin",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [ "//lib:lib" ],
+                "name" : "bin",
+                "srcs" : [ "main.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//lib:lib" : {
+                "buck.base_path" : "lib",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "name" : "lib",
+                "srcs" : [ "lib.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//unrelated:unrelated" : {
+                "buck.base_path" : "unrelated",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "name" : "unrelated",
+                "srcs" : [ "lib.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            }
+        }"#;
+
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let backend = crate::query::InMemory::new("nonexistent", all_rules);
+        let translation = translate_rules(
+            &backend,
+            None,
+            &[pattern("//bin:bin")],
+            &Filter::default(),
+            false,
+        )?;
+
+        assert_eq!(translation.buck_root, PathBuf::from("nonexistent"));
+        assert_eq!(translation.rules.len(), 2);
+        let files = translation
+            .files
+            .iter()
+            .map(|file| (file.path.to_str().unwrap(), file))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(files.len(), 3);
+        assert!(files.values().all(|file| file.existing.is_none()));
+        assert_eq!(
+            files["Cargo.toml"].contents,
+            r#"# @generated by transantlator
+
+[workspace]
+members = [
+    "bin",
+    "lib",
+]
+default-members = [
+    "bin",
+]
+"#
+        );
+        assert!(files["bin/Cargo.toml"]
+            .contents
+            .contains("[dependencies]\nlib = { path = \"../lib\" }\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn check_dependency_visibility() {
+        let input = r#"{
+            "//app:app" : {
+                "buck.base_path" : "app",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [ "//app:private", "//lib:private", "//lib:shared", "//lib:public" ],
+                "name" : "app",
+                "srcs" : [ "main.rs" ]
+            },
+            "//app:private" : {
+                "buck.base_path" : "app",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "name" : "private",
+                "srcs" : [ "lib.rs" ]
+            },
+            "//lib:private" : {
+                "buck.base_path" : "lib",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "name" : "private",
+                "srcs" : [ "private.rs" ],
+                "visibility" : [ "//lib/..." ]
+            },
+            "//lib:shared" : {
+                "buck.base_path" : "lib",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "name" : "shared",
+                "srcs" : [ "shared.rs" ],
+                "visibility" : [ "//app:" ]
+            },
+            "//lib:public" : {
+                "buck.base_path" : "lib",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [ "//lib:private" ],
+                "name" : "public",
+                "srcs" : [ "public.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            }
+        }"#;
 
-use std::collections::HashMap;
-use std::ffi::OsStr;
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::path::Path;
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new(""), &all_rules);
+        let warnings = check_visibility(&cx);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code, "not-visible");
+        assert_eq!(warnings[0].target, Some(target("//app:app")));
+        assert_eq!(
+            warnings[0].message,
+            "//app:app depends on //lib:private, which is not visible to it"
+        );
+    }
+
+    #[test]
+    fn filter_rules() -> Result<(), failure::Error> {
+        let input = r#"{
+            "//app:app" : {
+                "buck.base_path" : "app",
+                "buck.direct_dependencies" : [ "//third-party:cpp" ],
+                "buck.type" : "rust_binary",
+                "deps" : [ "//third-party:cpp" ],
+                "name" : "app",
+                "srcs" : [ "main.rs" ]
+            },
+            "//third-party:cpp" : {
+                "buck.base_path" : "third-party",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "cxx_library",
+                "deps" : [],
+                "name" : "cpp"
+            }
+        }"#;
 
-use crate::buck::{BuildRule, BuildTarget};
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let backend = crate::query::InMemory::new("nonexistent", all_rules);
+        let filter = Filter {
+            exclude: vec!["//third-party/...".parse()?],
+            ..Default::default()
+        };
+        let translation = translate_rules(&backend, None, &[pattern("//app:app")], &filter, false)?;
 
-// Not a const since format! needs a literal and doesn't work with const &str
-macro_rules! toml_header {
-    () => {
-        r#"[package]
-name = "{}"
-version = "0.1.0"
-authors = ["Example <author@example.com>"]
-"#
-    };
-}
+        assert_eq!(translation.rules.len(), 1);
+        let app = &translation.rules[&target("//app:app")];
+        assert!(app.common.deps.is_empty());
+        assert!(app.direct_dependencies.is_empty());
 
-pub fn translate_rules<'a>(
-    buck_root: &Path,
-    rules: impl Iterator<Item = (&'a BuildTarget, &'a BuildRule)>,
-) -> Result<(), failure::Error> {
-    let mut rules_by_dir = HashMap::<_, Vec<_>>::new();
+        let filter = Filter {
+            include: vec!["//third-party:".parse()?],
+            ..Default::default()
+        };
+        assert!(filter.allows(&target("//third-party:cpp")));
+        assert!(!filter.allows(&target("//app:app")));
 
-    for (target, rule) in rules {
-        rules_by_dir
-            .entry(&rule.base_path)
-            .or_default()
-            .push((target, rule));
+        Ok(())
     }
 
-    eprintln!("rules_by_dir: {:#?}", rules_by_dir);
-
-    for (base_dir, rules) in rules_by_dir {
-        let contents = translate_buildfile(base_dir, &rules)?;
+    #[test]
+    fn report_unsupported_rules() {
+        let input = r#"{
+            "//app:app" : {
+                "buck.base_path" : "app",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [ "//cpp:wrapper", "//lib:lib" ],
+                "name" : "app",
+                "srcs" : [ "main.rs" ]
+            },
+            "//lib:lib" : {
+                "buck.base_path" : "lib",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [ "//cpp:core" ],
+                "name" : "lib",
+                "srcs" : [ "lib.rs" ]
+            },
+            "//cpp:wrapper" : {
+                "buck.base_path" : "cpp",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "cxx_library",
+                "deps" : [ "//cpp:core" ],
+                "name" : "wrapper"
+            },
+            "//cpp:core" : {
+                "buck.base_path" : "cpp",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "cxx_library",
+                "deps" : [],
+                "name" : "core"
+            }
+        }"#;
 
-        let mut file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(buck_root.join(base_dir).join("Cargo.toml"))?;
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let unsupported = unsupported_rules(&all_rules);
+        assert_eq!(unsupported.len(), 2);
+        assert_eq!(
+            unsupported[&target("//cpp:core")],
+            [&target("//app:app"), &target("//lib:lib")]
+        );
+        assert_eq!(
+            unsupported[&target("//cpp:wrapper")],
+            [&target("//app:app")]
+        );
 
-        file.write_all(contents.as_bytes())?;
+        let cx = Context::new(Path::new(""), &all_rules);
+        let manifests = translate_packages(&cx).unwrap();
+        assert_eq!(manifests.len(), 2);
+        assert_eq!(manifests[Path::new("app")].dependencies.len(), 1);
+        assert!(manifests[Path::new("lib")].dependencies.is_empty());
     }
 
-    Ok(())
-}
+    #[test]
+    fn collect_package_errors() {
+        let input = r#"{
+            "//a:lib" : {
+                "buck.base_path" : "a",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "features" : [ "foo" ],
+                "name" : "lib",
+                "srcs" : [ "lib.rs" ]
+            },
+            "//a:bin" : {
+                "buck.base_path" : "a",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [],
+                "name" : "bin",
+                "srcs" : [ "main.rs" ]
+            },
+            "//b:bin" : {
+                "buck.base_path" : "b",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [ "//missing:lib" ],
+                "name" : "bin",
+                "srcs" : [ "main.rs" ]
+            }
+        }"#;
 
-pub fn translate_buildfile(
-    dir: &Path,
-    rules: &[(&BuildTarget, &BuildRule)],
-) -> Result<String, failure::Error> {
-    let libs: Vec<&BuildRule> = rules
-        .iter()
-        .map(|(_, r)| *r)
-        .filter(|r| r.typ.is_library())
-        .collect();
-    let bins: Vec<&BuildRule> = rules
-        .iter()
-        .map(|(_, r)| *r)
-        .filter(|r| r.typ.is_binary() && !r.typ.is_test())
-        .collect();
-    // Reject multiple libraries in the same buildfile
-    // TODO: Generate Cargo workspace for those?
-    if libs.len() > 1 {
-        let names = libs
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new(""), &all_rules);
+        let errors = translate_packages(&cx).unwrap_err();
+        let errors = errors
             .iter()
-            .map(|r| r.common.name.as_ref())
-            .collect::<Vec<&str>>();
-        return Err(failure::format_err!(
-            "Multiple rust_library() in single buildfile is not yet supported ({}, {})",
-            dir.display(),
-            names.join(", ")
-        ));
+            .map(|error| (error.code, error.target.as_ref().unwrap().to_string()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                ("conflicting-features", String::from("//a:lib")),
+                ("missing-dependency", String::from("//b:bin"))
+            ]
+        );
     }
 
-    let default_bin = || {
-        bins.iter()
-            .find(|b| b.typ.crate_root().unwrap().file_name() == Some(&OsStr::new("main.rs")))
-    };
-    let default_rule = libs.get(0).or_else(default_bin).or_else(|| bins.get(0));
-    let default_rule = default_rule.ok_or_else(|| failure::format_err!(
-            "Couldn't find a fitting default Rule for buildfile {}",
-            dir.display()
-        )
-    )?;
-
-    let pkg_name = default_rule.typ.krate().unwrap();
+    #[test]
+    fn split_package_cycles() -> Result<(), failure::Error> {
+        let input = r#"{
+            "//a:a" : {
+                "buck.base_path" : "a",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [ "//b:b" ],
+                "name" : "a",
+                "srcs" : [ "lib.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//b:b" : {
+                "buck.base_path" : "b",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "name" : "b",
+                "srcs" : [ "lib.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//b:tool" : {
+                "buck.base_path" : "b",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [ "//a:a", "//b:b" ],
+                "name" : "tool",
+                "srcs" : [ "tool.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            }
+        }"#;
 
-    // FIXME: Use buffered writer
-    let mut toml = format!(toml_header!(), pkg_name);
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new(""), &all_rules);
+        let errors = check_package_cycles(&cx);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].message,
+            "Packages a, b depend on each other through //a:a -> //b:b, //b:tool -> //a:a"
+        );
 
-    if let Some(&lib) = libs.get(0) {
-        toml.push_str("\n");
-        toml.push_str("[lib]\n");
-        toml.push_str(&format!(r#"name = "{}""#, lib.typ.krate().unwrap()));
-        toml.push_str("\n");
-        toml.push_str(&format!(
-            r#"path = "{}""#,
-            lib.typ.crate_root().unwrap().display()
-        ));
-        toml.push_str("\n");
-    }
+        let cx = cx.split_package_cycles();
+        assert!(check_package_cycles(&cx).is_empty());
+        assert_eq!(
+            cx.package_dir(&target("//b:tool")),
+            Some(Path::new("b/cargo-bins/tool"))
+        );
+        assert_eq!(
+            translate_packages(&cx)?[Path::new("b/cargo-bins/tool")].to_string(),
+            r#"[package]
+name = "tool"
+version = "0.1.0"
+authors = ["Example <author@example.com>"]
 
-    for bin in bins {
-        toml.push_str("\n");
-        toml.push_str("[[bin]]\n");
-        toml.push_str(&format!(r#"name = "{}""#, bin.typ.krate().unwrap()));
-        toml.push_str("\n");
-        toml.push_str(&format!(
-            r#"path = "{}""#,
-            bin.typ.crate_root().unwrap().display()
-        ));
-        toml.push_str("\n");
-    }
+[[bin]]
+name = "tool"
+path = "../../tool.rs"
 
-    // TODO: For now reject code with unit tests having different deps than
-    // bins/libs
-    Ok(toml)
-}
+[dependencies]
+a = { path = "../../../a" }
+b = { path = "../.." }
+"#
+        );
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::collections::BTreeMap;
+        Ok(())
+    }
 
     #[test]
-    fn reject_multiple_libs() {
+    fn translate_linker_flags() -> Result<(), failure::Error> {
         let input = r#"{
-            "//dir:lib1" : {
+            "//dir:lib" : {
                 "buck.base_path" : "dir",
                 "buck.direct_dependencies" : [],
                 "buck.type" : "rust_library",
                 "deps" : [],
-                "name" : "lib1",
-                "srcs" : [ "src/lib.rs" ],
-                "visibility" : [ "PUBLIC" ]
+                "name" : "lib",
+                "srcs" : [ "lib.rs" ],
+                "tests" : [ "//dir:test" ]
             },
-            "//dir:lib2" : {
+            "//dir:bin" : {
                 "buck.base_path" : "dir",
                 "buck.direct_dependencies" : [],
-                "buck.type" : "rust_library",
+                "buck.type" : "rust_binary",
                 "deps" : [],
-                "name" : "lib2",
-                "srcs" : [ "src/lib.rs" ],
-                "visibility" : [ "PUBLIC" ]
+                "linker_flags" : [ "-Wl,-T,link.ld" ],
+                "name" : "bin",
+                "srcs" : [ "main.rs" ]
+            },
+            "//dir:tool" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [],
+                "name" : "tool",
+                "srcs" : [ "tool.rs" ]
+            },
+            "//dir:test" : {
+                "buck.base_path" : "dir",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_test",
+                "deps" : [],
+                "linker_flags" : [ "-lfoo" ],
+                "name" : "test",
+                "srcs" : [ "test.rs" ]
             }
         }"#;
-        let rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
-        let rules = rules.iter().collect::<Vec<(_, _)>>();
 
-        let result = translate_buildfile(Path::new("dummy"), &rules);
-        assert!(result.is_err());
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new(""), &all_rules);
+        let manifest = &translate_packages(&cx)?[Path::new("dir")];
+        assert_eq!(
+            manifest.package.as_ref().unwrap().build,
+            Some(PathBuf::from("build.rs"))
+        );
+        assert_eq!(
+            manifest.build_script.as_ref().unwrap().instructions(),
+            [
+                "cargo:rustc-link-arg-bin=bin=-Wl,-T,link.ld",
+                "cargo:rustc-link-arg-tests=-lfoo"
+            ]
+        );
+
+        Ok(())
     }
 
     #[test]
-    fn translate_pkg_name_with_lib() -> Result<(), failure::Error> {
+    fn translate_lint_flags() -> Result<(), failure::Error> {
         let input = r#"{
-            "//dir:bin1" : {
-                "buck.base_path" : "dir",
-                "buck.direct_dependencies" : [],
-                "buck.type" : "rust_binary",
-                "deps" : [],
-                "name" : "bin1",
-                "srcs" : [ "src/main.rs" ],
-                "visibility" : [ "PUBLIC" ]
-            },
-            "//dir:lib1" : {
+            "//dir:lib" : {
                 "buck.base_path" : "dir",
                 "buck.direct_dependencies" : [],
                 "buck.type" : "rust_library",
                 "deps" : [],
-                "name" : "lib1",
-                "srcs" : [ "src/lib.rs" ],
-                "visibility" : [ "PUBLIC" ]
+                "name" : "lib",
+                "rustc_flags" : [ "-D", "warnings", "-Adead-code" ],
+                "srcs" : [ "lib.rs" ]
             },
-            "//dir:aux_bin" : {
+            "//dir:bin" : {
                 "buck.base_path" : "dir",
                 "buck.direct_dependencies" : [],
                 "buck.type" : "rust_binary",
                 "deps" : [],
-                "name" : "aux_bin",
-                "srcs" : [ "aux_bin.rs" ],
-                "visibility" : [ "PUBLIC" ]
+                "name" : "bin",
+                "rustc_flags" : [ "-Dwarnings", "-Wclippy::pedantic" ],
+                "srcs" : [ "main.rs" ]
             }
         }"#;
 
-        let rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
-        let rules: BTreeMap<_, _> = rules.into_iter().collect(); // deterministic
-        let rules = rules.iter().collect::<Vec<(_, _)>>();
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new(""), &all_rules);
         assert_eq!(
-            translate_buildfile(Path::new("dummy"), &rules)?,
+            translate_packages(&cx)?[Path::new("dir")].to_string(),
             r#"[package]
-name = "lib1"
+name = "lib"
 version = "0.1.0"
 authors = ["Example <author@example.com>"]
 
 [lib]
-name = "lib1"
-path = "src/lib.rs"
+name = "lib"
+path = "lib.rs"
 
 [[bin]]
-name = "aux_bin"
-path = "aux_bin.rs"
+name = "bin"
+path = "main.rs"
 
-[[bin]]
-name = "bin1"
-path = "src/main.rs"
+[lints.clippy]
+pedantic = { level = "warn", priority = -1 }
+
+[lints.rust]
+dead_code = "warn"
+warnings = { level = "deny", priority = -1 }
 "#
         );
 
+        let warnings = check_lints(&cx);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code, "conflicting-lints");
+        assert!(warnings[0]
+            .message
+            .contains(" clippy::pedantic, dead_code, so"));
+
         Ok(())
     }
 
     #[test]
-    fn translate_pkg_name_with_bin() -> Result<(), failure::Error> {
+    fn translate_cfg_flags() -> Result<(), failure::Error> {
         let input = r#"{
-            "//dir:aux_bin" : {
+            "//dir:lib" : {
                 "buck.base_path" : "dir",
                 "buck.direct_dependencies" : [],
-                "buck.type" : "rust_binary",
+                "buck.type" : "rust_library",
                 "deps" : [],
-                "name" : "aux_bin",
-                "srcs" : [ "aux_bin.rs" ],
-                "visibility" : [ "PUBLIC" ]
+                "name" : "lib",
+                "rustc_flags" : [ "--cfg", "foo", "--cfg=mode=\"fast\"" ],
+                "srcs" : [ "lib.rs" ]
             },
-            "//dir:bin1" : {
+            "//dir:bin" : {
                 "buck.base_path" : "dir",
                 "buck.direct_dependencies" : [],
                 "buck.type" : "rust_binary",
                 "deps" : [],
-                "name" : "bin1",
-                "srcs" : [ "src/main.rs" ],
-                "visibility" : [ "PUBLIC" ]
+                "name" : "bin",
+                "rustc_flags" : [ "--cfg", "mode=\"fast\"", "--cfg", "foo" ],
+                "srcs" : [ "main.rs" ]
+            },
+            "//other:bin" : {
+                "buck.base_path" : "other",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [],
+                "name" : "bin",
+                "rustc_flags" : [ "--cfg", "foo" ],
+                "srcs" : [ "main.rs" ]
+            },
+            "//other:tool" : {
+                "buck.base_path" : "other",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [],
+                "name" : "tool",
+                "srcs" : [ "tool.rs" ]
             }
         }"#;
 
-        let rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
-        let rules: BTreeMap<_, _> = rules.into_iter().collect(); // deterministic
-        let rules = rules.iter().collect::<Vec<(_, _)>>();
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new(""), &all_rules);
+        let errors = translate_packages(&cx).unwrap_err();
+        assert_eq!(errors.error_count(), 1);
+        let error = errors.iter().next().unwrap();
+        assert_eq!(error.code, "conflicting-cfgs");
+        assert_eq!(error.target, Some("//other:tool".parse()?));
+
+        let package = packages(&cx).remove(0);
+        assert_eq!(package.dir, Path::new("dir"));
         assert_eq!(
-            translate_buildfile(Path::new("dummy"), &rules)?,
-            r#"[package]
-name = "bin1"
-version = "0.1.0"
-authors = ["Example <author@example.com>"]
+            translate_build_script(&package)
+                .unwrap()
+                .unwrap()
+                .instructions(),
+            [
+                "cargo:rustc-cfg=foo",
+                "cargo:rustc-cfg=mode=\"fast\"",
+                "cargo::rustc-check-cfg=cfg(foo)",
+                "cargo::rustc-check-cfg=cfg(mode, values(\"fast\"))",
+            ]
+        );
 
-[[bin]]
-name = "aux_bin"
-path = "aux_bin.rs"
+        Ok(())
+    }
 
-[[bin]]
-name = "bin1"
-path = "src/main.rs"
+    #[test]
+    fn translate_codegen_flags() -> Result<(), failure::Error> {
+        let input = r#"{
+            "//bin:bin" : {
+                "buck.base_path" : "bin",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [ "//lib:lib" ],
+                "name" : "bin",
+                "rustc_flags" : [ "-C", "opt-level=3", "-Cpanic=abort", "-C", "lto" ],
+                "srcs" : [ "main.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            },
+            "//lib:lib" : {
+                "buck.base_path" : "lib",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "name" : "lib",
+                "rustc_flags" : [ "-Ccodegen-units=1", "-Cpanic=unwind", "-Ctarget-cpu=native" ],
+                "srcs" : [ "lib.rs" ],
+                "visibility" : [ "PUBLIC" ]
+            }
+        }"#;
+
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let backend = crate::query::InMemory::new("nonexistent", all_rules);
+        let translation = translate_rules(
+            &backend,
+            None,
+            &[pattern("//bin:bin")],
+            &Filter::default(),
+            false,
+        )?;
+
+        let root = translation
+            .files
+            .iter()
+            .find(|file| file.path == Path::new("Cargo.toml"))
+            .unwrap();
+        assert!(root.contents.ends_with(
+            r#"
+[profile.dev]
+panic = "abort"
+
+[profile.release]
+lto = true
+panic = "abort"
+
+[profile.release.package.bin]
+opt-level = 3
+
+[profile.release.package.lib]
+codegen-units = 1
 "#
+        ));
+
+        let codes = translation
+            .diagnostics
+            .iter()
+            .map(|d| (d.code, d.target.clone()));
+        assert_eq!(
+            codes.collect::<Vec<_>>(),
+            [
+                ("unsupported-rustc-flags", Some("//lib:lib".parse()?)),
+                ("conflicting-codegen-flags", None),
+            ]
         );
 
         Ok(())
     }
 
     #[test]
-    fn translate_lib() -> Result<(), failure::Error> {
+    fn report_missing_crate_root() {
         let input = r#"{
-            "//dir:lib1" : {
+            "//dir:bin" : {
                 "buck.base_path" : "dir",
                 "buck.direct_dependencies" : [],
+                "buck.type" : "rust_binary",
+                "deps" : [],
+                "name" : "bin",
+                "srcs" : [ "a.rs", "b.rs" ]
+            }
+        }"#;
+
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let cx = Context::new(Path::new(""), &all_rules);
+        let errors = translate_packages(&cx).unwrap_err();
+        let codes = errors.iter().map(|error| error.code).collect::<Vec<_>>();
+        assert_eq!(codes, ["no-crate-root"]);
+    }
+
+    #[test]
+    fn report_invalid_manifests() -> Result<(), failure::Error> {
+        let input = r#"{
+            "//a:a" : {
+                "buck.base_path" : "a",
+                "buck.direct_dependencies" : [],
                 "buck.type" : "rust_library",
                 "deps" : [],
-                "name" : "lib1",
-                "srcs" : [ "src/lib.rs" ],
-                "visibility" : [ "PUBLIC" ]
+                "name" : "a",
+                "srcs" : [ "lib.rs" ]
+            },
+            "//b:b" : {
+                "buck.base_path" : "b",
+                "buck.direct_dependencies" : [],
+                "buck.type" : "rust_library",
+                "deps" : [],
+                "name" : "b",
+                "srcs" : [ "lib.rs" ]
             }
         }"#;
-        let rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
-        let rules = rules.iter().collect::<Vec<(_, _)>>();
-        assert_eq!(
-            translate_buildfile(Path::new("dummy"), &rules[..1])?,
-            r#"[package]
-name = "lib1"
-version = "0.1.0"
-authors = ["Example <author@example.com>"]
 
-[lib]
-name = "lib1"
-path = "src/lib.rs"
-"#
+        let root = std::env::temp_dir().join(format!("transantlator-{}", std::process::id()));
+        for dir in &["a", "b"] {
+            fs::create_dir_all(root.join(dir))?;
+            fs::write(root.join(dir).join("Cargo.toml"), "[package\n")?;
+        }
+
+        let all_rules = crate::buck::from_bytes(input.as_bytes()).unwrap();
+        let backend = crate::query::InMemory::new(&root, all_rules);
+        let patterns = [pattern("//a:a"), pattern("//b:b")];
+        let translation = translate_rules(&backend, None, &patterns, &Filter::default(), false);
+        fs::remove_dir_all(&root)?;
+
+        let diagnostics = translation?.diagnostics;
+        let errors = diagnostics
+            .iter()
+            .map(|d| (d.code, d.base_path.clone().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                ("invalid-manifest", PathBuf::from("a")),
+                ("invalid-manifest", PathBuf::from("b")),
+            ]
         );
 
         Ok(())